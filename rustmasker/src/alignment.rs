@@ -0,0 +1,457 @@
+// SAM/BAM records
+//
+// Unaligned BAM (e.g. from ONT basecallers) carries the read in the SEQ and
+// QUAL fields next to aux tags such as modification calls. Records are masked
+// with the same byte-level functions as FASTQ reads: quality scores are
+// converted to Phred+33 for masking and back to raw Phred scores afterwards,
+// and all other fields are left untouched, except base modification calls
+// (MM/ML), which refer to base occurrences and are updated to match.
+
+use noodles::sam::alignment::record::data::field::Tag;
+use noodles::sam::alignment::record_buf::data::field::value::Array;
+use noodles::sam::alignment::record_buf::data::field::Value;
+use noodles::sam::alignment::RecordBuf;
+
+use crate::intervals::Interval;
+
+/// Base modification calls
+pub const MM_TAG: Tag = Tag::new(b'M', b'M');
+/// Base modification probabilities
+pub const ML_TAG: Tag = Tag::new(b'M', b'L');
+/// Sequence length the MM/ML tags apply to
+pub const MN_TAG: Tag = Tag::new(b'M', b'N');
+
+/// Offset between raw Phred scores (SAM/BAM records) and FASTQ quality characters
+pub const PHRED_OFFSET: u8 = 33;
+
+/// Read name, or "*" for records without one
+pub fn read_name(record: &RecordBuf) -> Vec<u8> {
+    record
+        .name()
+        .map_or_else(|| b"*".to_vec(), |name| name.to_vec())
+}
+
+/// Sequence and Phred+33 qualities of a record
+/// Qualities are empty if the record has none ('*').
+pub fn sequence_and_quality(record: &RecordBuf) -> (Vec<u8>, Vec<u8>) {
+    let seq = record.sequence().as_ref().to_vec();
+    let qual = record
+        .quality_scores()
+        .as_ref()
+        .iter()
+        .map(|&q| q.saturating_add(PHRED_OFFSET))
+        .collect();
+    (seq, qual)
+}
+
+/// Replace the sequence and qualities of a record (qualities given as Phred+33)
+pub fn set_sequence_and_quality(record: &mut RecordBuf, seq: &[u8], qual: &[u8]) {
+    *record.sequence_mut() = seq.to_vec().into();
+    *record.quality_scores_mut() = qual
+        .iter()
+        .map(|&q| q.saturating_sub(PHRED_OFFSET))
+        .collect::<Vec<u8>>()
+        .into();
+}
+
+/// Set an integer aux tag, replacing any existing value
+pub fn set_int_tag(record: &mut RecordBuf, tag: Tag, value: usize) {
+    let value = i32::try_from(value).unwrap_or(i32::MAX);
+    record.data_mut().insert(tag, Value::Int32(value));
+}
+
+/// Set aux tags given in SAM text form (`TG:i:1`, `TG:f:0.5` or `TG:Z:text`)
+pub fn set_text_tags(record: &mut RecordBuf, tags: &[String]) -> Result<(), String> {
+    for text in tags {
+        let mut fields = text.splitn(3, ':');
+        let (Some(tag), Some(ty), Some(value)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(format!("invalid tag '{}'", text));
+        };
+        let tag = parse_tag(tag)?;
+        let value = match ty {
+            "i" => Value::Int32(value.parse().map_err(|_| format!("invalid integer tag '{}'", text))?),
+            "f" => Value::Float(value.parse().map_err(|_| format!("invalid float tag '{}'", text))?),
+            "Z" => Value::String(value.into()),
+            _ => return Err(format!("unsupported tag type in '{}'", text)),
+        };
+        record.data_mut().insert(tag, value);
+    }
+    Ok(())
+}
+
+/// Parse a two-character aux tag name (a letter followed by a letter or digit)
+pub fn parse_tag(s: &str) -> Result<Tag, String> {
+    match s.as_bytes() {
+        &[a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphanumeric() => Ok(Tag::new(a, b)),
+        _ => Err(format!(
+            "invalid tag '{}': expected a letter followed by a letter or digit",
+            s
+        )),
+    }
+}
+
+/// Complement of a base (IUPAC ambiguity codes other than N are left as-is)
+fn complement(base: u8) -> u8 {
+    match base.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' | b'U' => b'A',
+        other => other,
+    }
+}
+
+/// Whether a base in the original read orientation counts as an occurrence of the MM base
+fn is_occurrence(base: u8, mm_base: u8) -> bool {
+    match mm_base {
+        b'N' => base.is_ascii_alphabetic(),
+        b'U' => base == b'T' || base == b'U',
+        _ => base == mm_base,
+    }
+}
+
+/// Number of modification codes in an MM group header after the strand (e.g. "mh" → 2, "27551" → 1)
+fn code_count(codes: &[u8]) -> Result<usize, String> {
+    if codes.is_empty() {
+        Err("missing modification code".to_string())
+    } else if codes.iter().all(u8::is_ascii_digit) {
+        // ChEBI identifier
+        Ok(1)
+    } else if codes.iter().all(u8::is_ascii_alphabetic) {
+        Ok(codes.len())
+    } else {
+        Err(format!("invalid modification codes '{}'", String::from_utf8_lossy(codes)))
+    }
+}
+
+/// Update MM/ML base modification calls after masking (and trimming) a read
+///
+/// MM skip counts refer to occurrences of a base in the read as sequenced (reverse
+/// complemented if `reverse` is set), so masking a base to N shifts the count of every
+/// later call. `original` is the SEQ before masking, `masked` the SEQ afterwards,
+/// `intervals` the masked intervals of the original SEQ and `offset` the number of bases
+/// trimmed from the start of SEQ. Calls on masked (including bases that were already N) or
+/// trimmed bases are dropped (with their ML probabilities); all other calls are re-encoded
+/// against the masked sequence, so the modification state of every remaining base is unchanged.
+/// Groups in implicit mode (`.` or no mode) that lose calls switch to `?`, since the bases of
+/// the dropped calls would otherwise read as unmodified.
+///
+/// Returns the new MM and ML values.
+pub fn update_modifications(
+    mm: &[u8],
+    ml: &[u8],
+    original: &[u8],
+    masked: &[u8],
+    intervals: &[Interval],
+    offset: usize,
+    reverse: bool,
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    if offset + masked.len() > original.len() {
+        return Err("masked sequence does not fit in the original sequence".to_string());
+    }
+
+    // Sequences in the original read orientation
+    let orient = |seq: &[u8]| -> Vec<u8> {
+        if reverse {
+            seq.iter().rev().map(|&b| complement(b)).collect()
+        } else {
+            seq.iter().map(|b| b.to_ascii_uppercase()).collect()
+        }
+    };
+    let old_seq = orient(original);
+    let new_seq = orient(masked);
+    // Oriented position in the original read → oriented position in the masked read
+    let trimmed_start = if reverse { original.len() - offset - masked.len() } else { offset };
+    let new_position = |j: usize| -> Option<usize> {
+        let k = j.checked_sub(trimmed_start).filter(|&k| k < new_seq.len())?;
+        let pos = if reverse { old_seq.len() - 1 - j } else { j };
+        (!intervals.iter().any(|iv| iv.start <= pos && pos < iv.end)).then_some(k)
+    };
+
+    let mut new_mm = Vec::with_capacity(mm.len());
+    let mut new_ml = Vec::with_capacity(ml.len());
+    let mut ml_pos = 0;
+
+    for group in mm.split(|&b| b == b';').filter(|g| !g.is_empty()) {
+        let mut fields = group.split(|&b| b == b',');
+        let header = fields.next().unwrap_or(&[]);
+        let (mm_base, codes) = match header {
+            [base, b'+' | b'-', rest @ ..] => (base.to_ascii_uppercase(), rest),
+            _ => {
+                return Err(format!("invalid MM group '{}'", String::from_utf8_lossy(group)));
+            }
+        };
+        let (codes, explicit) = match codes.strip_suffix(b"?") {
+            Some(codes) => (codes, true),
+            None => (codes.strip_suffix(b".").unwrap_or(codes), false),
+        };
+        let n_codes = code_count(codes)?;
+
+        let old_occurrences = (0..old_seq.len()).filter(|&j| is_occurrence(old_seq[j], mm_base));
+        let mut new_rank = vec![None; new_seq.len()];
+        for (rank, k) in (0..new_seq.len())
+            .filter(|&k| is_occurrence(new_seq[k], mm_base))
+            .enumerate()
+        {
+            new_rank[k] = Some(rank);
+        }
+
+        let mut calls = Vec::new();
+        let mut dropped = false;
+        let mut occurrences = old_occurrences;
+        let mut previous_rank: Option<usize> = None;
+        for skip in fields {
+            let skip: usize = std::str::from_utf8(skip)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| format!("invalid MM skip count '{}'", String::from_utf8_lossy(skip)))?;
+            let j = occurrences
+                .nth(skip)
+                .ok_or("MM calls refer to more bases than the sequence contains")?;
+            let probabilities = ml
+                .get(ml_pos..ml_pos + n_codes)
+                .ok_or("ML has fewer values than MM calls")?;
+            ml_pos += n_codes;
+
+            if let Some(rank) = new_position(j).and_then(|k| new_rank[k]) {
+                let new_skip = rank - previous_rank.map_or(0, |r| r + 1);
+                calls.extend_from_slice(format!(",{}", new_skip).as_bytes());
+                new_ml.extend_from_slice(probabilities);
+                previous_rank = Some(rank);
+            } else {
+                dropped = true;
+            }
+        }
+        if dropped && !explicit {
+            new_mm.extend_from_slice(&header[..2]);
+            new_mm.extend_from_slice(codes);
+            new_mm.push(b'?');
+        } else {
+            new_mm.extend_from_slice(header);
+        }
+        new_mm.extend_from_slice(&calls);
+        new_mm.push(b';');
+    }
+
+    if ml_pos != ml.len() {
+        return Err("ML has more values than MM calls".to_string());
+    }
+    Ok((new_mm, new_ml))
+}
+
+/// Update the MM/ML (and MN) tags of a record after its SEQ has been masked
+///
+/// `original` is the SEQ before masking, `intervals` its masked intervals and `offset` the
+/// number of bases trimmed from its start. Records without an MM tag are left unchanged.
+/// Fails if the record's MN tag does not match the length of `original`, as its calls may
+/// then refer to another sequence.
+pub fn update_modification_tags(
+    record: &mut RecordBuf,
+    original: &[u8],
+    intervals: &[Interval],
+    offset: usize,
+) -> Result<(), String> {
+    let Some(Value::String(mm)) = record.data().get(&MM_TAG) else {
+        return Ok(());
+    };
+    let ml = match record.data().get(&ML_TAG) {
+        Some(Value::Array(Array::UInt8(ml))) => ml.as_slice(),
+        Some(_) => return Err("ML tag is not a uint8 array".to_string()),
+        None => &[],
+    };
+    if let Some(mn) = record.data().get(&MN_TAG) {
+        if mn.as_int() != Some(original.len() as i64) {
+            return Err("MN tag does not match the SEQ length".to_string());
+        }
+    }
+    let reverse = record.flags().is_reverse_complemented();
+    let (new_mm, new_ml) = update_modifications(
+        mm,
+        ml,
+        original,
+        record.sequence().as_ref(),
+        intervals,
+        offset,
+        reverse,
+    )?;
+
+    let has_ml = record.data().get(&ML_TAG).is_some();
+    let new_len = record.sequence().len();
+    let data = record.data_mut();
+    data.insert(MM_TAG, Value::String(new_mm.into()));
+    if has_ml {
+        data.insert(ML_TAG, Value::Array(Array::UInt8(new_ml)));
+    }
+    if data.get(&MN_TAG).is_some() {
+        data.insert(MN_TAG, Value::Int32(i32::try_from(new_len).unwrap_or(i32::MAX)));
+    }
+    Ok(())
+}
+
+/// Remove the MM/ML/MN base modification tags from a record
+pub fn strip_modification_tags(record: &mut RecordBuf) {
+    let data = record.data_mut();
+    for tag in [MM_TAG, ML_TAG, MN_TAG] {
+        data.remove(&tag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask_range;
+
+    #[test]
+    fn test_mask_record_preserves_tags() {
+        let mut record = RecordBuf::builder()
+            .set_name("read1")
+            .set_sequence(b"ACGTACGT".to_vec().into())
+            .set_quality_scores(vec![40; 8].into())
+            .build();
+        record
+            .data_mut()
+            .insert(Tag::new(b'R', b'G'), Value::String("rg1".into()));
+
+        let (mut seq, mut qual) = sequence_and_quality(&record);
+        assert_eq!(qual, b"IIIIIIII");
+        mask_range(&mut seq, &mut qual, 2, 5);
+        set_sequence_and_quality(&mut record, &seq, &qual);
+        set_int_tag(&mut record, parse_tag("XM").unwrap(), 3);
+
+        assert_eq!(record.sequence().as_ref(), b"ACNNNCGT");
+        assert_eq!(record.quality_scores().as_ref(), &[40, 40, 2, 2, 2, 40, 40, 40]);
+        assert_eq!(read_name(&record), b"read1");
+        assert_eq!(record.data().len(), 2);
+        assert_eq!(record.data().get(b"XM"), Some(&Value::Int32(3)));
+    }
+
+    #[test]
+    fn test_missing_quality_and_tag_parsing() {
+        let record = RecordBuf::builder()
+            .set_sequence(b"ACGT".to_vec().into())
+            .build();
+        let (seq, qual) = sequence_and_quality(&record);
+        assert_eq!(seq, b"ACGT");
+        assert!(qual.is_empty());
+        assert_eq!(read_name(&record), b"*");
+
+        let mut record = record;
+        set_text_tags(&mut record, &["mf:f:0.5".to_string(), "mr:Z:0-2".to_string()]).unwrap();
+        assert_eq!(record.data().get(b"mf"), Some(&Value::Float(0.5)));
+        assert_eq!(record.data().get(b"mr"), Some(&Value::String("0-2".into())));
+        assert!(set_text_tags(&mut record, &["mi:B:1".to_string()]).is_err());
+
+        assert!(parse_tag("mc").is_ok());
+        assert!(parse_tag("1X").is_err());
+        assert!(parse_tag("XYZ").is_err());
+    }
+
+    #[test]
+    fn test_update_modifications_drops_masked_calls() {
+        // C occurrences at 1, 3, 6, 9; calls on the 1st, 2nd and 4th C
+        let original = b"ACGCAGCTACGT";
+        let masked = b"ACGNAGCTACGT";
+        let (mm, ml) =
+            update_modifications(b"C+m?,0,0,1;", &[200, 10, 30], original, masked, &[Interval::new(3, 4)], 0, false)
+                .unwrap();
+        // The call on the 2nd C (masked) is dropped; the 4th C is now the 3rd
+        assert_eq!(mm, b"C+m?,0,1;");
+        assert_eq!(ml, vec![200, 30]);
+    }
+
+    #[test]
+    fn test_update_modifications_multiple_codes_and_trimming() {
+        let original = b"CCACCACC";
+        // Two bases trimmed from the start, one masked
+        let masked = b"ACNACC";
+        let (mm, ml) = update_modifications(
+            b"C+mh.,0,1,0,1;A+a,0;",
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9],
+            original,
+            masked,
+            &[Interval::new(4, 5)],
+            2,
+            false,
+        )
+        .unwrap();
+        // C calls on C#0 (trimmed), C#2 (→ new C#0), C#3 (masked), C#5 (→ new C#2); the
+        // dropped calls make the skipped Cs unknown, while the A group is unchanged
+        assert_eq!(mm, b"C+mh?,0,1;A+a,0;");
+        assert_eq!(ml, vec![3, 4, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_update_modifications_implicit_mode() {
+        let original = b"ACGCAGCTACGT";
+        let masked = b"ACGNAGCTACGT";
+        let intervals = [Interval::new(3, 4)];
+        // Implicit mode switches to '?' when calls are dropped, so skipped Cs are not unmodified
+        let (mm, _) = update_modifications(b"C+m.,0,0;", &[1, 2], original, masked, &intervals, 0, false).unwrap();
+        assert_eq!(mm, b"C+m?,0;");
+        let (mm, _) = update_modifications(b"C+m,1;", &[1], original, masked, &intervals, 0, false).unwrap();
+        assert_eq!(mm, b"C+m?;");
+        // Groups without dropped calls keep their mode
+        let (mm, _) = update_modifications(b"C+m.,0;", &[1], original, masked, &intervals, 0, false).unwrap();
+        assert_eq!(mm, b"C+m.,0;");
+    }
+
+    #[test]
+    fn test_update_modification_tags_stale_mn() {
+        let original = b"ACGCAGCT";
+        let mut record = RecordBuf::builder()
+            .set_sequence(b"ACGNAGCT".to_vec().into())
+            .build();
+        let data = record.data_mut();
+        data.insert(MM_TAG, Value::String("C+m.,0,0;".into()));
+        data.insert(ML_TAG, Value::Array(Array::UInt8(vec![1, 2])));
+        data.insert(MN_TAG, Value::Int32(12));
+        // MN of another sequence: the calls cannot be updated
+        assert!(update_modification_tags(&mut record, original, &[Interval::new(3, 4)], 0).is_err());
+
+        record.data_mut().insert(MN_TAG, Value::UInt8(8));
+        update_modification_tags(&mut record, original, &[Interval::new(3, 4)], 0).unwrap();
+        assert_eq!(record.data().get(&MM_TAG), Some(&Value::String("C+m?,0;".into())));
+        assert_eq!(record.data().get(&MN_TAG), Some(&Value::Int32(8)));
+    }
+
+    #[test]
+    fn test_update_modifications_reverse_strand() {
+        // The read as sequenced is the reverse complement of SEQ
+        let original = b"CAACC";
+        let masked = b"CAANC";
+        let intervals = [Interval::new(3, 4)];
+        let (mm, ml) = update_modifications(b"G+o,1,0;", &[50, 60], original, masked, &intervals, 0, true).unwrap();
+        // As sequenced: GGTTG → GNTTG; calls on G#1 (masked) and G#2 (→ new G#1)
+        assert_eq!(mm, b"G+o?,1;");
+        assert_eq!(ml, vec![60]);
+
+        assert!(update_modifications(b"C+m,5;", &[1], original, masked, &intervals, 0, false).is_err());
+        assert!(update_modifications(b"C+m,0;", &[1, 2], original, masked, &intervals, 0, false).is_err());
+    }
+
+    #[test]
+    fn test_update_modifications_already_n() {
+        // Bases 2-4 masked, of which 2 and 3 were already N; N+ calls count every base
+        let original = b"ACNNGTCA";
+        let masked = b"ACNNNTCA";
+        let intervals = [Interval::new(2, 5)];
+        let (mm, ml) = update_modifications(
+            b"N+a?,2,0,1;C+m?,0,0;",
+            &[10, 20, 30, 40, 50],
+            original,
+            masked,
+            &intervals,
+            0,
+            false,
+        )
+        .unwrap();
+        // Calls on the masked Ns at 2 and 3 are dropped; the call at 5 keeps its rank
+        assert_eq!(mm, b"N+a?,5;C+m?,0,0;");
+        assert_eq!(ml, vec![30, 40, 50]);
+
+        // An N outside the masked intervals keeps its call
+        let (mm, _) =
+            update_modifications(b"N+a?,2;", &[10], original, masked, &[Interval::new(4, 5)], 0, false).unwrap();
+        assert_eq!(mm, b"N+a?,2;");
+    }
+}
@@ -0,0 +1,132 @@
+// Batch processing of several input files in one run
+//
+// Jobs come either from multiple inputs written to an output directory, or
+// from a manifest TSV. Each job is masked with the shared thread pool of the
+// run, optionally with its own command-line overrides. Inputs can also be
+// concatenated into a single output, labelled by the file they came from.
+
+use std::path::Path;
+
+use crate::formats::{Compression, SequenceFormat};
+
+/// One input file and where to write its masked reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchJob {
+    pub input: String,
+    pub output: String,
+    /// Extra command-line options for this file (e.g. `--window 64`)
+    pub params: Vec<String>,
+}
+
+/// Parse a manifest: tab-separated input path, output path and optional per-file options
+///
+/// The options column is split on whitespace (no quoting). Blank lines and lines starting
+/// with '#' are ignored.
+pub fn parse_manifest(text: &str) -> Result<Vec<BatchJob>, String> {
+    let mut jobs = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 2 || fields.len() > 3 || fields[0].is_empty() || fields[1].is_empty() {
+            return Err(format!(
+                "manifest line {}: expected input, output and optional options separated by tabs",
+                i + 1
+            ));
+        }
+        jobs.push(BatchJob {
+            input: fields[0].to_string(),
+            output: fields[1].to_string(),
+            params: fields
+                .get(2)
+                .map(|params| params.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+        });
+    }
+    Ok(jobs)
+}
+
+/// Job writing an input to a file of the same name in `dir`
+pub fn job_in_dir(input: &str, dir: &str) -> Result<BatchJob, String> {
+    let name = Path::new(input)
+        .file_name()
+        .ok_or_else(|| format!("input '{}' has no file name", input))?;
+    let output = Path::new(dir).join(name);
+    // Refuse to overwrite the input
+    if let (Ok(a), Ok(b)) = (Path::new(input).canonicalize(), output.canonicalize()) {
+        if a == b {
+            return Err(format!("output for '{}' would overwrite the input", input));
+        }
+    }
+    Ok(BatchJob {
+        input: input.to_string(),
+        output: output.to_string_lossy().into_owned(),
+        params: Vec::new(),
+    })
+}
+
+/// Check that no two jobs write to the same output
+pub fn check_outputs(jobs: &[BatchJob]) -> Result<(), String> {
+    for (i, job) in jobs.iter().enumerate() {
+        if jobs[..i].iter().any(|other| other.output == job.output) {
+            return Err(format!("output '{}' is used by more than one input", job.output));
+        }
+    }
+    Ok(())
+}
+
+/// Label of an input file: its name without compression and sequence format extensions
+pub fn file_label(path: &str) -> String {
+    let mut name = Path::new(path)
+        .file_name()
+        .map_or_else(|| path.to_string(), |name| name.to_string_lossy().into_owned());
+    if Compression::from_path(&name).is_some() {
+        name.truncate(name.rfind('.').unwrap_or(name.len()));
+    }
+    if SequenceFormat::from_path(&name).is_some() {
+        name.truncate(name.rfind('.').unwrap_or(name.len()));
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let text = "# input\toutput\toptions\n\
+                    a.fastq.gz\tout/a.fastq.gz\n\
+                    \n\
+                    b.bam\tout/b.bam\t--window 64  --sdust\r\n";
+        let jobs = parse_manifest(text).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].output, "out/a.fastq.gz");
+        assert!(jobs[0].params.is_empty());
+        assert_eq!(jobs[1].params, vec!["--window", "64", "--sdust"]);
+        assert!(check_outputs(&jobs).is_ok());
+
+        assert!(parse_manifest("a.fastq\n").is_err());
+        assert!(parse_manifest("a.fastq\t\n").is_err());
+        let duplicate = parse_manifest("a.fq\tout.fq\nb.fq\tout.fq\n").unwrap();
+        assert!(check_outputs(&duplicate).is_err());
+    }
+
+    #[test]
+    fn test_job_in_dir() {
+        let job = job_in_dir("data/lane1.fastq.gz", "masked").unwrap();
+        assert_eq!(Path::new(&job.output), Path::new("masked/lane1.fastq.gz"));
+        assert!(job_in_dir("src/batch.rs", "src").is_err());
+        assert!(job_in_dir("/", "masked").is_err());
+    }
+
+    #[test]
+    fn test_file_label() {
+        assert_eq!(file_label("data/lane1.R1.fastq.gz"), "lane1.R1");
+        assert_eq!(file_label("sample.bam"), "sample");
+        assert_eq!(file_label("reads.zst"), "reads");
+        assert_eq!(file_label("reads.txt"), "reads.txt");
+    }
+}
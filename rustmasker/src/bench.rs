@@ -0,0 +1,130 @@
+// Throughput benchmark results
+//
+// `rustmasker bench` times entropy masking for each combination of method,
+// k, window, read length and thread count, and writes one TSV row per
+// combination. A previous table can be given as a baseline, so the same
+// sweep run on two versions shows performance regressions.
+
+use std::collections::HashMap;
+
+/// Header of the benchmark table
+pub const BENCH_COLUMNS: &str =
+    "method\tk\twindow\tread_length\tthreads\treads\tbases\tmasked_bases\tseconds\treads_per_second\tmegabases_per_second";
+
+/// Timing of one benchmark configuration (the fastest of its runs)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub method: String,
+    pub k: usize,
+    pub window: usize,
+    /// Mean read length
+    pub read_length: usize,
+    pub threads: usize,
+    pub reads: usize,
+    pub bases: usize,
+    pub masked_bases: usize,
+    pub seconds: f64,
+}
+
+impl BenchResult {
+    /// Configuration columns identifying the row in another table
+    pub fn key(&self) -> String {
+        bench_key(&self.method, self.k, self.window, self.read_length, self.threads)
+    }
+
+    pub fn reads_per_second(&self) -> f64 {
+        self.reads as f64 / self.seconds
+    }
+
+    pub fn megabases_per_second(&self) -> f64 {
+        self.bases as f64 / self.seconds / 1e6
+    }
+
+    /// Table row (without a trailing newline)
+    pub fn row(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.0}\t{:.2}",
+            self.method,
+            self.k,
+            self.window,
+            self.read_length,
+            self.threads,
+            self.reads,
+            self.bases,
+            self.masked_bases,
+            self.seconds,
+            self.reads_per_second(),
+            self.megabases_per_second()
+        )
+    }
+}
+
+fn bench_key(method: &str, k: usize, window: usize, read_length: usize, threads: usize) -> String {
+    format!("{}/k{}/w{}/l{}/j{}", method, k, window, read_length, threads)
+}
+
+/// Megabases per second of each configuration of a benchmark table
+pub fn parse_bench_table(text: &str) -> Result<HashMap<String, f64>, String> {
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().ok_or("empty benchmark table")?.split('\t').collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|&c| c == name)
+            .ok_or_else(|| format!("benchmark table has no '{}' column", name))
+    };
+    let columns = [
+        column("method")?,
+        column("k")?,
+        column("window")?,
+        column("read_length")?,
+        column("threads")?,
+        column("megabases_per_second")?,
+    ];
+
+    let mut table = HashMap::new();
+    for (i, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let field = |c: usize| fields.get(columns[c]).copied().unwrap_or("");
+        let number = |c: usize| {
+            field(c).parse::<usize>().map_err(|_| format!("benchmark table line {}: invalid number", i + 2))
+        };
+        let key = bench_key(field(0), number(1)?, number(2)?, number(3)?, number(4)?);
+        let speed = field(5)
+            .parse::<f64>()
+            .map_err(|_| format!("benchmark table line {}: invalid megabases_per_second", i + 2))?;
+        table.insert(key, speed);
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_table_round_trip() {
+        let result = BenchResult {
+            method: "array".to_string(),
+            k: 5,
+            window: 80,
+            read_length: 1000,
+            threads: 4,
+            reads: 2000,
+            bases: 2_000_000,
+            masked_bases: 600_000,
+            seconds: 0.5,
+        };
+        assert_eq!(result.megabases_per_second(), 4.0);
+        let text = format!("{}\n{}\n", BENCH_COLUMNS, result.row());
+        let table = parse_bench_table(&text).unwrap();
+        assert_eq!(table.get(&result.key()), Some(&4.0));
+
+        assert!(parse_bench_table("").is_err());
+        assert!(parse_bench_table("method\tk\n").is_err());
+        assert!(parse_bench_table(&format!("{}\narray\tx\n", BENCH_COLUMNS)).is_err());
+    }
+}
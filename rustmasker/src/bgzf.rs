@@ -0,0 +1,245 @@
+// Parallel BGZF writer
+//
+// BGZF (blocked gzip, as written by bgzip and samtools) is a series of gzip
+// members holding at most 64 KiB each, so it stays readable by any gzip
+// decoder while allowing random access. Blocks are independent, which makes
+// them easy to compress in parallel: full blocks are batched and compressed
+// with rayon, then written in order. The writer can also record a `.gzi`
+// index of block offsets (the format of `bgzip -i`).
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use flate2::write::DeflateEncoder;
+use flate2::Crc;
+use rayon::prelude::*;
+
+/// Maximum uncompressed bytes per block (as in htslib, leaves room for incompressible data)
+pub const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// Empty block marking the end of a BGZF file
+pub const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Compress one block of at most [`BGZF_BLOCK_SIZE`] bytes into a complete BGZF member
+pub fn compress_block(data: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(
+        Vec::with_capacity(data.len() / 2 + 64),
+        flate2::Compression::new(level),
+    );
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    // 18-byte header, compressed data, 8-byte footer
+    let block_size = 18 + compressed.len() + 8;
+    let bsize = u16::try_from(block_size - 1)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "BGZF block too large"))?;
+    let mut block = Vec::with_capacity(block_size);
+    block.extend_from_slice(&[0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0x00, b'B', b'C', 0x02, 0x00]);
+    block.extend_from_slice(&bsize.to_le_bytes());
+    block.extend_from_slice(&compressed);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(block)
+}
+
+/// BGZF writer compressing blocks in parallel, with an optional `.gzi` index
+///
+/// The end-of-file block (and the index) are written by [`BgzfWriter::finish`]; a writer
+/// dropped without finishing leaves the output incomplete.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    level: u32,
+    /// Uncompressed data not yet compressed
+    buf: Vec<u8>,
+    /// Number of full blocks compressed together
+    batch_blocks: usize,
+    /// Offsets of written blocks, if an index is requested
+    index: Option<(PathBuf, Vec<(u64, u64)>)>,
+    compressed_offset: u64,
+    uncompressed_offset: u64,
+    finished: bool,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    /// Create a writer compressing at `level` (0-9) on the rayon thread pool
+    pub fn new(inner: W, level: u32) -> Self {
+        let batch_blocks = rayon::current_num_threads() * 4;
+        Self {
+            inner,
+            level: level.min(9),
+            buf: Vec::with_capacity(BGZF_BLOCK_SIZE * batch_blocks),
+            batch_blocks,
+            index: None,
+            compressed_offset: 0,
+            uncompressed_offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Also write a `.gzi` index to `path` when finishing
+    pub fn with_index<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.index = Some((path.into(), Vec::new()));
+        self
+    }
+
+    /// The underlying writer, e.g. to finish it after this writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Compress and write buffered data: full blocks only, or everything if `all`
+    fn write_blocks(&mut self, all: bool) -> io::Result<()> {
+        let n_full = self.buf.len() / BGZF_BLOCK_SIZE;
+        let end = if all { self.buf.len() } else { n_full * BGZF_BLOCK_SIZE };
+        if end == 0 {
+            return Ok(());
+        }
+
+        let level = self.level;
+        let blocks: Vec<io::Result<Vec<u8>>> = self.buf[..end]
+            .par_chunks(BGZF_BLOCK_SIZE)
+            .map(|data| compress_block(data, level))
+            .collect();
+
+        for (data, block) in self.buf[..end].chunks(BGZF_BLOCK_SIZE).zip(blocks) {
+            let block = block?;
+            if let Some((_, offsets)) = self.index.as_mut() {
+                // The first block (0, 0) is implicit in the index
+                if self.compressed_offset > 0 {
+                    offsets.push((self.compressed_offset, self.uncompressed_offset));
+                }
+            }
+            self.inner.write_all(&block)?;
+            self.compressed_offset += block.len() as u64;
+            self.uncompressed_offset += data.len() as u64;
+        }
+        self.buf.drain(..end);
+        Ok(())
+    }
+
+    /// Write the remaining data, the end-of-file block and the index
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.write_blocks(true)?;
+        self.inner.write_all(&BGZF_EOF)?;
+        self.inner.flush()?;
+
+        if let Some((path, offsets)) = &self.index {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write_gzi(&mut writer, offsets)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= BGZF_BLOCK_SIZE * self.batch_blocks {
+            self.write_blocks(false)?;
+        }
+        Ok(data.len())
+    }
+
+    /// Writes full blocks only, so flushing does not create short blocks
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_blocks(false)?;
+        self.inner.flush()
+    }
+}
+
+/// Write a `.gzi` index: the number of entries, then (compressed, uncompressed) offset
+/// pairs for every block after the first, all as little-endian u64
+pub fn write_gzi<W: Write>(writer: &mut W, offsets: &[(u64, u64)]) -> io::Result<()> {
+    writer.write_all(&(offsets.len() as u64).to_le_bytes())?;
+    for &(compressed, uncompressed) in offsets {
+        writer.write_all(&compressed.to_le_bytes())?;
+        writer.write_all(&uncompressed.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    fn test_data() -> Vec<u8> {
+        (0..20_000)
+            .flat_map(|i| format!("@read{}\nACGTACGTTTGACA\n+\nIIIIIIIIIIIIII\n", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_bgzf_round_trip() {
+        let data = test_data();
+        let mut output = Vec::new();
+        {
+            let mut writer = BgzfWriter::new(&mut output, 1);
+            // Small writes and flushes must not produce short blocks
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+                writer.flush().unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        assert!(output.ends_with(&BGZF_EOF));
+
+        // Walk the blocks: all but the last data block are full
+        let mut pos = 0;
+        let mut sizes = Vec::new();
+        while pos < output.len() {
+            assert_eq!(&output[pos + 12..pos + 14], b"BC");
+            let bsize = u16::from_le_bytes([output[pos + 16], output[pos + 17]]) as usize + 1;
+            let isize = &output[pos + bsize - 4..pos + bsize];
+            sizes.push(u32::from_le_bytes(isize.try_into().unwrap()) as usize);
+            pos += bsize;
+        }
+        assert_eq!(sizes.len(), data.len().div_ceil(BGZF_BLOCK_SIZE) + 1);
+        assert!(sizes[..sizes.len() - 2].iter().all(|&s| s == BGZF_BLOCK_SIZE));
+
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(output.as_slice()).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn test_gzi_index() {
+        let data = test_data();
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rustmasker_bgzf_{}.gz", std::process::id()));
+        let gzi_path = dir.join(format!("rustmasker_bgzf_{}.gz.gzi", std::process::id()));
+        {
+            let file = File::create(&path).unwrap();
+            let mut writer = BgzfWriter::new(file, 6).with_index(&gzi_path);
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let index = noodles::bgzf::gzi::fs::read(&gzi_path).unwrap();
+        let mut reader = noodles::bgzf::io::Reader::new(File::open(&path).unwrap());
+        // Seek to a position in the third block and read from there
+        let pos = (2 * BGZF_BLOCK_SIZE + 100) as u64;
+        let virtual_position = index.query(pos).unwrap();
+        assert_eq!(virtual_position.uncompressed(), 100);
+        reader.seek(virtual_position).unwrap();
+        let mut buf = [0u8; 50];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[pos as usize..pos as usize + 50]);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&gzi_path).unwrap();
+    }
+}
@@ -1,10 +1,68 @@
-use std::io::{self, BufWriter, Write, IsTerminal};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write, IsTerminal};
+use std::ffi::OsString;
+use std::collections::HashSet;
 use std::fs::File;
-use needletail::{parse_fastx_stdin, parse_fastx_file};
-use gzp::{deflate::Gzip, par::compress::ParCompressBuilder, Compression as GzpCompression};
-use clap::{Parser, ValueEnum};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use needletail::parser::Format;
+use needletail::{parse_fastx_reader, FastxReader};
+use noodles::sam::alignment::io::Write as AlignmentWrite;
+use noodles::sam::alignment::record::data::field::Tag;
+use noodles::sam::alignment::RecordBuf;
+use noodles::{bam, sam};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Args as ClapArgs, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use rayon::prelude::*;
-use rustmasker::{mask_sequence_auto, mask_sequence_array, mask_sequence};
+use rustmasker::alignment::{
+    parse_tag, read_name, sequence_and_quality, set_int_tag, set_sequence_and_quality, set_text_tags,
+    strip_modification_tags, update_modification_tags,
+};
+use rustmasker::batch::{check_outputs, file_label, job_in_dir, parse_manifest};
+use rustmasker::bench::{parse_bench_table, BenchResult, BENCH_COLUMNS};
+use rustmasker::bgzf::BgzfWriter;
+use rustmasker::calibrate::{threshold_sweep, Calibration, HISTOGRAM_BINS};
+use rustmasker::compare::{diff_read, n_runs, Comparison, DiffClass};
+use rustmasker::config::{parse_config, ConfigValue};
+use rustmasker::decompress::{is_bgzf, parallel_bgzf_reader, parallel_gzip_reader, threaded_reader, InputPosition};
+use rustmasker::evaluate::{parse_truth_bed, Evaluation};
+use rustmasker::fastx::{append_tags, plus_line, write_fastq, HeaderAnnotation};
+use rustmasker::formats::{
+    compress_writer, decompress_reader, read_head, Compression, FinishWrite, SequenceFormat,
+};
+use rustmasker::homopolymer::{find_homopolymer_runs, HomopolymerRun, MinRunLength, RunLengthHistogram};
+use rustmasker::intervals::Interval;
+use rustmasker::outfmt::{write_bedgraph, write_bedgraph_header, DustFormat};
+use rustmasker::simulate::{
+    complexity_mix, parse_length_range, sequence, simulate_read, Embedding, LengthModel, Rng,
+};
+use rustmasker::pipeline::{CombineRule, Detector, LabeledInterval, MaskPipeline, PostProcess};
+use rustmasker::progress::{CountingReader, Progress};
+use rustmasker::stats::{files_json, write_files_json, FileStats, HomopolymerStats, MaskingStats, PolyXStats, ShortReadStats};
+use rustmasker::tails::{find_poly_x_tails, PolyXTail, TailConfig};
+use rustmasker::{entropy_mask_intervals, entropy_track, mask_range, EntropyMethod, ShortReadPolicy};
+
+/// Verbosity of messages other than errors: 0 with -q, 1 by default (warnings), 2 or more with -v
+static VERBOSITY: AtomicU8 = AtomicU8::new(1);
+
+/// Print a warning to stderr unless -q is given
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if VERBOSITY.load(Ordering::Relaxed) >= 1 {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Print an informational message to stderr with -v
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if VERBOSITY.load(Ordering::Relaxed) >= 2 {
+            eprintln!($($arg)*);
+        }
+    };
+}
 
 /// Method for entropy calculation
 #[derive(ValueEnum, Clone, Debug)]
@@ -17,18 +75,233 @@ enum Method {
     Hashmap,
 }
 
+impl From<&Method> for EntropyMethod {
+    fn from(method: &Method) -> Self {
+        match method {
+            Method::Auto => EntropyMethod::Auto,
+            Method::Array => EntropyMethod::Array,
+            Method::Hashmap => EntropyMethod::Hashmap,
+        }
+    }
+}
+
+/// How entropy masking treats reads shorter than the window
+#[derive(ValueEnum, Clone, Debug)]
+enum ShortReads {
+    /// Mask all or nothing based on whole-read entropy (BBMask-compatible)
+    WholeRead,
+    /// Slide a window of half the read length (reads of k bases or fewer are not masked)
+    ShrinkWindow,
+    /// Never mask reads shorter than the window
+    Never,
+    /// Always mask reads shorter than the window
+    Always,
+}
+
+impl From<&ShortReads> for ShortReadPolicy {
+    fn from(short_reads: &ShortReads) -> Self {
+        match short_reads {
+            ShortReads::WholeRead => ShortReadPolicy::WholeRead,
+            ShortReads::ShrinkWindow => ShortReadPolicy::ShrinkWindow,
+            ShortReads::Never => ShortReadPolicy::Never,
+            ShortReads::Always => ShortReadPolicy::Always,
+        }
+    }
+}
+
+/// What to do with homopolymer runs
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum HomopolymerMode {
+    /// Do not detect homopolymer runs
+    Off,
+    /// Mask runs (in addition to entropy masking)
+    Mask,
+    /// Count runs in the stats output without masking them
+    Report,
+}
+
+/// What to do with poly-X tails
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum PolyXMode {
+    /// Do not detect poly-X tails
+    Off,
+    /// Mask tails (in addition to entropy masking)
+    Mask,
+    /// Remove tails from the read (bases and qualities)
+    Trim,
+}
+
+/// Read ends searched for poly-X tails
+#[derive(ValueEnum, Clone, Debug)]
+enum TailEnds {
+    /// Both 5' and 3' ends
+    Both,
+    /// 5' end only
+    FivePrime,
+    /// 3' end only
+    ThreePrime,
+}
+
+/// What to do with MM/ML base modification tags of masked SAM/BAM records
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum ModTags {
+    /// Drop calls on masked or trimmed bases and re-encode the rest
+    Update,
+    /// Remove MM/ML/MN tags from records with masked or trimmed bases
+    Strip,
+}
+
+/// Output compression
+#[derive(ValueEnum, Clone, Debug)]
+enum OutputFormat {
+    /// Uncompressed
+    None,
+    Gzip,
+    /// Blocked gzip (bgzip-compatible, random access)
+    Bgzf,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl From<&OutputFormat> for Compression {
+    fn from(format: &OutputFormat) -> Self {
+        match format {
+            OutputFormat::None => Compression::None,
+            OutputFormat::Gzip => Compression::Gzip,
+            OutputFormat::Bgzf => Compression::Bgzf,
+            OutputFormat::Zstd => Compression::Zstd,
+            OutputFormat::Bzip2 => Compression::Bzip2,
+            OutputFormat::Xz => Compression::Xz,
+        }
+    }
+}
+
 /// Mask low-complexity regions in FASTQ reads using entropy calculation
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_override_self = true, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Masking options, as for `rustmasker mask`
+    #[command(flatten)]
+    mask: Args,
+}
+
+impl Cli {
+    /// Masking options of `rustmasker [mask|stats] ...`, or None for other subcommands
+    fn into_mask_args(self) -> Option<Args> {
+        match self.command {
+            None => Some(self.mask),
+            Some(Command::Mask(args)) => Some(*args),
+            Some(Command::Stats(args)) => {
+                let mut args = *args;
+                args.discard_output = true;
+                Some(args)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Masking options of `rustmasker [mask|stats|evaluate] ...`
+    fn mask_options_mut(&mut self) -> Option<&mut Args> {
+        match &mut self.command {
+            None => Some(&mut self.mask),
+            Some(Command::Mask(args)) | Some(Command::Stats(args)) => Some(args),
+            Some(Command::Evaluate(args)) => Some(&mut args.mask),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Built-in option sets of --preset
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Preset {
+    /// BBMask entropy masking: window 80, threshold 0.70, k 5
+    Bbmask,
+    /// Symmetric DUST only: window 64, score threshold 20
+    Sdust,
+    /// As NCBI dustmasker: symmetric DUST (window 64, threshold 20) written as dustmasker intervals
+    Dustmasker,
+    /// Nanopore reads: entropy or DUST, homopolymers of 12+ bases, gaps up to 10 bases filled
+    OntStrict,
+}
+
+impl Preset {
+    /// Command-line masking options of the preset
+    fn options(&self) -> &'static [&'static str] {
+        match self {
+            Preset::Bbmask => &["--window", "80", "--threshold", "0.70", "--kmer", "5", "--short-reads", "whole-read"],
+            Preset::Sdust | Preset::Dustmasker => {
+                &["--no-entropy", "--sdust", "--sdust-window", "64", "--sdust-threshold", "20"]
+            }
+            Preset::OntStrict => &[
+                "--window", "80", "--threshold", "0.70", "--kmer", "5", "--sdust", "--combine", "union",
+                "--homopolymer", "mask", "--homopolymer-min-run", "12", "--merge-gap", "10",
+            ],
+        }
+    }
+
+    /// Output options of the preset, only used when reads are written (not by stats or evaluate)
+    fn output_options(&self) -> &'static [&'static str] {
+        match self {
+            Preset::Dustmasker => &["--outfmt", "interval"],
+            _ => &[],
+        }
+    }
+}
+
+/// Masking options
+#[derive(ClapArgs, Debug, Clone)]
 struct Args {
-    /// Input FASTQ file (plain or gzipped). If not specified, reads from stdin
-    #[arg(short = 'i', long)]
-    input: Option<String>,
+    /// Input FASTQ, SAM or BAM file (plain or gzipped, auto-detected). If not specified, reads from stdin.
+    /// Several files can be given with --output-dir
+    #[arg(short = 'i', long, num_args = 1..)]
+    input: Vec<String>,
 
-    /// Output FASTQ file. If not specified, writes to stdout (gzipped)
+    /// Output file. If not specified, writes to stdout. The format follows the extension
+    /// (.bam, .sam, .fastq/.fq); otherwise it is the same as the input format
     #[arg(short = 'o', long)]
     output: Option<String>,
 
+    /// Mask several input files (-i) into files of the same name in this directory
+    #[arg(long)]
+    output_dir: Option<String>,
+
+    /// Mask the files listed in this TSV: input path, output path and optional per-file options
+    #[arg(long)]
+    manifest: Option<String>,
+
+    /// Mask several input files (-i) one after the other into a single output
+    #[arg(long, overrides_with = "no_concat")]
+    concat: bool,
+
+    /// Undo --concat (e.g. from a preset or config file)
+    #[arg(long, overrides_with = "concat")]
+    no_concat: bool,
+
+    /// With --concat: prefix read IDs with the label of their input file and a ':'
+    #[arg(long, overrides_with = "no_label_reads")]
+    label_reads: bool,
+
+    /// Undo --label-reads
+    #[arg(long, overrides_with = "label_reads")]
+    no_label_reads: bool,
+
+    /// With --concat: comma-separated labels of the input files (default: file names without
+    /// extensions). Implies --label-reads
+    #[arg(long, value_delimiter = ',')]
+    labels: Vec<String>,
+
+    /// TOML file of options by long name (e.g. `window = 64`); command-line options override it
+    #[arg(long)]
+    config: Option<String>,
+
+    /// Built-in option set; the config file and command-line options override it
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
     /// Window size for entropy calculation
     #[arg(short = 'w', long, default_value_t = 80)]
     window: usize,
@@ -45,11 +318,49 @@ struct Args {
     #[arg(short = 'm', long, value_enum, default_value = "auto")]
     method: Method,
 
-    /// Gzip compression level (0-9, where 0=no compression, 1=fast, 9=max compression).
-    /// If not specified: stdout is uncompressed, .gz files use level 1 (fast compression).
+    /// Compression level (0-9, where 0=no compression, 1=fast, 9=max compression), mapped to
+    /// each codec's range. If not specified: stdout is uncompressed, .gz/.zst/.bz2/.xz files
+    /// use level 1 (fast compression).
     #[arg(short = 'c', long)]
     compression_level: Option<u32>,
 
+    /// Output compression (none, gzip, bgzf, zstd, bzip2, xz). Defaults to the output
+    /// extension (.gz, .bgz, .zst, .bz2, .xz), or gzip if only -c is given
+    #[arg(long, value_enum)]
+    output_format: Option<OutputFormat>,
+
+    /// Write a .gzi index of the BGZF output to <output>.gzi (requires BGZF output to a file)
+    #[arg(long, overrides_with = "no_gzi")]
+    gzi: bool,
+
+    /// Undo --gzi
+    #[arg(long, overrides_with = "gzi")]
+    no_gzi: bool,
+
+    /// Write reads without any masking or trimming here instead of to the main output
+    #[arg(long)]
+    clean_output: Option<String>,
+
+    /// Compression level for --clean-output (default: as -c)
+    #[arg(long)]
+    clean_level: Option<u32>,
+
+    /// Write partially masked reads here instead of to the main output
+    #[arg(long)]
+    partial_output: Option<String>,
+
+    /// Compression level for --partial-output (default: as -c)
+    #[arg(long)]
+    partial_level: Option<u32>,
+
+    /// Write fully masked reads (no unmasked bases left) here instead of to the main output
+    #[arg(long)]
+    full_output: Option<String>,
+
+    /// Compression level for --full-output (default: as -c)
+    #[arg(long)]
+    full_level: Option<u32>,
+
     /// Number of reads to process per chunk (controls memory usage)
     #[arg(short = 's', long, default_value_t = 1000)]
     chunk_size: usize,
@@ -57,219 +368,2384 @@ struct Args {
     /// Number of threads to use (default: auto-detect CPU cores)
     #[arg(short = 'j', long)]
     threads: Option<usize>,
+
+    /// Entropy masking of reads shorter than the window
+    #[arg(long, value_enum, default_value = "whole-read")]
+    short_reads: ShortReads,
+
+    /// Disable entropy masking (e.g. to use only --sdust or other detectors)
+    #[arg(long, overrides_with = "entropy")]
+    no_entropy: bool,
+
+    /// Undo --no-entropy (e.g. from a preset or config file)
+    #[arg(long, overrides_with = "no_entropy")]
+    entropy: bool,
+
+    /// Enable symmetric DUST (sdust) masking
+    #[arg(long, overrides_with = "no_sdust")]
+    sdust: bool,
+
+    /// Undo --sdust (e.g. from a preset or config file)
+    #[arg(long, overrides_with = "sdust")]
+    no_sdust: bool,
+
+    /// Window size for sdust
+    #[arg(long, default_value_t = 64)]
+    sdust_window: usize,
+
+    /// Score threshold for sdust (mask if score > threshold)
+    #[arg(long, default_value_t = 20)]
+    sdust_threshold: i32,
+
+    /// How to combine masking detectors: union, intersection, or min-overlap:N
+    /// (mask bases flagged by at least N detectors)
+    #[arg(long, default_value = "union")]
+    combine: CombineRule,
+
+    /// Unmask masked intervals shorter than this many bases (applied after gap merging)
+    #[arg(long, default_value_t = 0)]
+    min_mask_len: usize,
+
+    /// Merge masked intervals separated by at most this many unmasked bases
+    #[arg(long, default_value_t = 0)]
+    merge_gap: usize,
+
+    /// Extend each masked interval by this many bases on both sides
+    #[arg(long, default_value_t = 0)]
+    mask_padding: usize,
+
+    /// Homopolymer run handling (off, mask, or report)
+    #[arg(long, value_enum, default_value = "off")]
+    homopolymer: HomopolymerMode,
+
+    /// Minimum homopolymer run length: one value for all bases (e.g. 10) or
+    /// per-base values (e.g. A=12,C=8,G=8,T=12; unlisted bases are not detected)
+    #[arg(long, default_value = "10")]
+    homopolymer_min_run: MinRunLength,
+
+    /// Poly-X tail handling (off, mask, or trim)
+    #[arg(long, value_enum, default_value = "off")]
+    poly_x: PolyXMode,
+
+    /// Tail bases to detect (any of A, C, G, T)
+    #[arg(long, default_value = "AGT")]
+    poly_x_bases: String,
+
+    /// Minimum poly-X tail length
+    #[arg(long, default_value_t = 10)]
+    poly_x_min_len: usize,
+
+    /// Maximum fraction of mismatching bases within a poly-X tail
+    #[arg(long, default_value_t = 0.1)]
+    poly_x_mismatch_rate: f64,
+
+    /// Read ends searched for poly-X tails
+    #[arg(long, value_enum, default_value = "both")]
+    poly_x_ends: TailEnds,
+
+    /// Write run statistics as JSON to this file
+    #[arg(long)]
+    stats: Option<String>,
+
+    /// Write a per-read report (TSV) of masked intervals and the detectors that produced them
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Write the entropy of each window (-w, -k) as a bedGraph track to this file
+    #[arg(long)]
+    entropy_bedgraph: Option<String>,
+
+    /// Keep the entropy of every Nth window in the bedGraph track (1 to the window size)
+    #[arg(long, default_value_t = 1)]
+    entropy_step: usize,
+
+    /// Reproduce the content of the FASTQ '+' line (e.g. a repeated header) instead of a bare '+'
+    #[arg(long, overrides_with = "no_keep_plus")]
+    keep_plus: bool,
+
+    /// Undo --keep-plus
+    #[arg(long, overrides_with = "keep_plus")]
+    no_keep_plus: bool,
+
+    /// Write masked intervals in a dustmasker format (interval, acclist or fasta) instead of
+    /// masked reads
+    #[arg(long, value_enum)]
+    outfmt: Option<Outfmt>,
+
+    /// Append SAM-style masking tags to each read header (aux tags for SAM/BAM output). FASTQ
+    /// header comments other than SAM tags are dropped, so that `minimap2 -y` gets valid tags
+    #[arg(long, value_enum)]
+    annotate_header: Option<AnnotateHeader>,
+
+    /// SAM/BAM output: store the number of masked bases in this integer aux tag (e.g. XM)
+    #[arg(long, value_parser = parse_tag)]
+    masked_count_tag: Option<Tag>,
+
+    /// SAM/BAM output: MM/ML base modification tags of masked records (update or strip)
+    #[arg(long, value_enum, default_value = "update")]
+    mod_tags: ModTags,
+
+    /// Report reads, bases, masked fraction and throughput to stderr every SECONDS (default 30),
+    /// with the estimated completion for input files
+    #[arg(long, num_args = 0..=1, default_missing_value = "30", value_name = "SECONDS")]
+    progress: Option<u64>,
+
+    /// Print more messages to stderr (per-file summaries)
+    #[arg(short = 'v', long, action = ArgAction::Count, overrides_with = "quiet")]
+    verbose: u8,
+
+    /// Only print errors (and --progress reports) to stderr
+    #[arg(short = 'q', long, overrides_with = "verbose")]
+    quiet: bool,
+
+    /// Discard the masked reads and only report statistics (`rustmasker stats`)
+    #[arg(skip)]
+    discard_output: bool,
+
+    /// Effective options, echoed into the stats output
+    #[arg(skip)]
+    effective_config: Option<serde_json::Value>,
+
+    /// Command line the options were parsed from, extended by per-file manifest options
+    #[arg(skip)]
+    argv: Vec<OsString>,
 }
 
-/// A single FASTQ record with all its data
-#[derive(Clone)]
-struct FastqRecord {
-    id: Vec<u8>,
-    seq: Vec<u8>,
-    qual: Vec<u8>,
+impl Args {
+    /// Whether run statistics are produced (--stats, or the `stats` subcommand)
+    fn writes_stats(&self) -> bool {
+        self.stats.is_some() || self.discard_output
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+/// dustmasker-compatible output formats
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Outfmt {
+    /// '>header' followed by 'start - end' lines (0-based, inclusive)
+    Interval,
+    /// '>id<TAB>start<TAB>end' lines (0-based, inclusive)
+    Acclist,
+    /// FASTA with masked bases in lowercase
+    Fasta,
+}
 
-    // Validate k-mer size (u32 encoding supports up to k=15)
-    if args.kmer > 15 {
-        eprintln!("Error: k-mer size k={} exceeds maximum supported value (k ≤ 15)", args.kmer);
-        eprintln!("The u32 encoding uses 2 bits per base, limiting k to 15 bases (30 bits).");
-        eprintln!("For low-complexity masking, k=3 to k=7 is typically used.");
-        std::process::exit(1);
+impl From<Outfmt> for DustFormat {
+    fn from(outfmt: Outfmt) -> Self {
+        match outfmt {
+            Outfmt::Interval => DustFormat::Interval,
+            Outfmt::Acclist => DustFormat::Acclist,
+            Outfmt::Fasta => DustFormat::Fasta,
+        }
     }
+}
 
-    if args.kmer < 1 {
-        eprintln!("Error: k-mer size k={} is too small (k must be at least 1)", args.kmer);
-        std::process::exit(1);
-    }
+/// Masking metadata added to each read header
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum AnnotateHeader {
+    /// Masked fraction and number of masked intervals (mf:f, mi:i)
+    Summary,
+    /// Number and list of masked intervals (mi:i, mr:Z)
+    Intervals,
+    /// Masked fraction, number and list of masked intervals
+    Full,
+}
 
-    // Validate compression level if specified
-    if let Some(level) = args.compression_level {
-        if level > 9 {
-            eprintln!("Error: compression level {} is invalid (must be 0-9)", level);
-            std::process::exit(1);
+impl From<AnnotateHeader> for HeaderAnnotation {
+    fn from(annotation: AnnotateHeader) -> Self {
+        match annotation {
+            AnnotateHeader::Summary => HeaderAnnotation::Summary,
+            AnnotateHeader::Intervals => HeaderAnnotation::Intervals,
+            AnnotateHeader::Full => HeaderAnnotation::Full,
         }
     }
+}
 
-    // Validate chunk size
-    if args.chunk_size < 1 {
-        eprintln!("Error: chunk size must be at least 1");
-        std::process::exit(1);
-    }
+/// Subcommands (without one, the masking options of `mask` are used)
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Mask reads (the default)
+    #[command(args_override_self = true)]
+    Mask(Box<Args>),
+    /// Mask reads and report statistics (JSON) without writing the reads
+    #[command(args_override_self = true)]
+    Stats(Box<Args>),
+    /// Compare the masked bases (N) of two files with the same reads
+    #[command(after_help = "Exit status is 0 if the masking is the same, 1 if it differs and 2 if the files \
+                            could not be compared")]
+    Compare(CompareArgs),
+    /// Generate synthetic reads of low, medium and high complexity
+    Simulate(SimulateArgs),
+    /// Measure masking throughput
+    Bench(BenchArgs),
+    /// Score masking against truth intervals (BED) at base and interval level
+    #[command(args_override_self = true)]
+    Evaluate(Box<EvaluateArgs>),
+    /// Report the fraction of bases masked at a sweep of thresholds, windows and k to help
+    /// choose parameters
+    Calibrate(CalibrateArgs),
+}
 
-    if args.chunk_size > 100000 {
-        eprintln!("Warning: chunk size {} is very large and may use excessive memory", args.chunk_size);
-        eprintln!("Recommended range: 1000-10000 for most systems");
-    }
+#[derive(ClapArgs, Debug, Clone)]
+struct CompareArgs {
+    /// First masked file (FASTQ, SAM or BAM, plain or compressed)
+    first: String,
 
-    // Check if stdin is a terminal and no input file specified
-    if args.input.is_none() && std::io::stdin().is_terminal() {
-        eprintln!("Error: No input provided. Use -i to specify input file or pipe data to stdin.");
-        eprintln!();
-        eprintln!("Usage:");
-        eprintln!("  mask_fastq -i input.fastq[.gz] -o output.fastq [OPTIONS]");
-        eprintln!("  cat input.fastq[.gz] | mask_fastq [OPTIONS] > output.fastq");
-        eprintln!();
-        eprintln!("Note: Input can be plain or gzipped FASTQ (auto-detected)");
-        eprintln!();
-        eprintln!("Compression:");
-        eprintln!("  - stdout: uncompressed by default (use -c 1-9 to compress)");
-        eprintln!("  - .gz files: compressed at level 1 by default (use -c to override)");
-        eprintln!("  - other files: uncompressed (use -c 1-9 to compress)");
-        eprintln!();
-        eprintln!("Examples:");
-        eprintln!("  mask_fastq -i reads.fastq.gz -o masked.fastq -j 4         # uncompressed");
-        eprintln!("  mask_fastq -i reads.fastq.gz -o masked.fastq.gz -j 4     # compressed (level 1)");
-        eprintln!("  mask_fastq -i reads.fastq.gz -o masked.fastq.gz -c 6 -j 4  # compressed (level 6)");
-        eprintln!("  cat reads.fastq | mask_fastq -j 4 > masked.fastq         # uncompressed stdout");
-        eprintln!();
-        eprintln!("For full help, use: mask_fastq --help");
-        std::process::exit(1);
-    }
+    /// Second masked file, with the same reads in the same order
+    second: String,
 
-    // Configure thread pool if specified
-    if let Some(threads) = args.threads {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(threads)
-            .build_global()
-            .unwrap();
-    }
+    /// The unmasked input, to tell masking apart from Ns already in the reads
+    #[arg(long)]
+    original: Option<String>,
 
-    // Create reader from file or stdin
-    let mut reader = if let Some(input_path) = &args.input {
-        parse_fastx_file(input_path)?
-    } else {
-        parse_fastx_stdin()?
-    };
+    /// Window size used for masking (reads shorter than it are classified as short reads)
+    #[arg(short = 'w', long, default_value_t = 80)]
+    window: usize,
 
-    // Create writer to file or stdout
-    let writer: Box<dyn Write> = if let Some(output_path) = &args.output {
-        let output_file = File::create(output_path)?;
+    /// Write one line per differing read (TSV; compressed according to its extension)
+    #[arg(long)]
+    diffs: Option<String>,
 
-        // Determine if we should compress based on extension and -c flag
-        let should_compress = match args.compression_level {
-            Some(0) => false,  // Explicit -c 0: no compression
-            Some(_) => true,   // Explicit -c 1-9: compress
-            None => output_path.ends_with(".gz"),  // No -c flag: auto-detect from extension
-        };
+    /// Write the differing reads as FASTQ, from --original if given, else from the first file
+    #[arg(long)]
+    extract: Option<String>,
 
-        if should_compress {
-            let level = args.compression_level.unwrap_or(1);  // Default to level 1 for .gz files
-            // Use parallel compression with gzp
-            let mut builder = ParCompressBuilder::<Gzip>::new()
-                .compression_level(GzpCompression::new(level));
+    /// Only print the summary of differing reads by class
+    #[arg(short = 'q', long)]
+    quiet: bool,
+}
 
-            // Configure compression threads if specified
-            if let Some(threads) = args.threads {
-                builder = builder.num_threads(threads)?;
-            }
+#[derive(ClapArgs, Debug, Clone)]
+struct EvaluateArgs {
+    /// Truth BED: read name, start, end and optional category (e.g. from `simulate --truth`)
+    #[arg(long)]
+    truth: String,
 
-            let encoder = builder.from_writer(output_file);
-            Box::new(BufWriter::new(encoder))
-        } else {
-            Box::new(BufWriter::new(output_file))
-        }
-    } else {
-        // stdout: compress only if -c flag is specified with value > 0
-        let should_compress = match args.compression_level {
-            Some(0) | None => false,  // No -c or -c 0: uncompressed (NEW default)
-            Some(_) => true,          // -c 1-9: compressed
-        };
+    /// Fraction of a truth interval that must be masked for it to count as detected
+    #[arg(long, default_value_t = 0.5)]
+    min_overlap: f64,
 
-        if should_compress {
-            let level = args.compression_level.unwrap();
-            let stdout = io::stdout();
-            // Use parallel compression with gzp
-            let mut builder = ParCompressBuilder::<Gzip>::new()
-                .compression_level(GzpCompression::new(level));
+    /// Masking options, as for `rustmasker mask`
+    #[command(flatten)]
+    mask: Args,
+}
 
-            // Configure compression threads if specified
-            if let Some(threads) = args.threads {
-                builder = builder.num_threads(threads)?;
-            }
+#[derive(ClapArgs, Debug, Clone)]
+struct SimulateArgs {
+    /// Output file (default: stdout); compressed according to its extension
+    #[arg(short = 'o', long)]
+    output: Option<String>,
 
-            let encoder = builder.from_writer(stdout);
-            Box::new(BufWriter::new(encoder))
-        } else {
-            let stdout = io::stdout();
-            Box::new(BufWriter::new(stdout))
-        }
-    };
+    /// Number of reads to generate
+    #[arg(short = 'n', long, default_value_t = 10000)]
+    num_reads: usize,
 
-    let mut writer = writer;
+    /// Average read length
+    #[arg(short = 'l', long, default_value_t = 1000)]
+    read_length: usize,
 
-    // Process reads in chunks
-    let mut chunk: Vec<FastqRecord> = Vec::with_capacity(args.chunk_size);
+    /// Read length distribution
+    #[arg(long, value_enum, default_value = "uniform")]
+    length_model: LengthDistribution,
 
-    while let Some(record) = reader.next() {
-        let rec = record?;
+    /// Fraction of low-complexity reads (homopolymers, di- and trinucleotide repeats)
+    #[arg(long, default_value_t = 0.3)]
+    low_complexity: f64,
 
-        // Store the record
-        chunk.push(FastqRecord {
-            id: rec.id().to_vec(),
-            seq: rec.seq().to_vec(),
-            qual: rec.qual().unwrap_or(&[]).to_vec(),
-        });
+    /// Fraction of medium-complexity reads (repeats of 4-8 base units)
+    #[arg(long, default_value_t = 0.3)]
+    medium_complexity: f64,
 
-        // Process chunk when full
-        if chunk.len() >= args.chunk_size {
-            process_and_write_chunk(&mut chunk, &mut writer, &args)?;
-            chunk.clear();
+    /// Homopolymers embedded in high-complexity reads, per kilobase
+    #[arg(long, default_value_t = 0.0)]
+    homopolymers: f64,
+
+    /// Short tandem repeats (units of 2-6 bases) embedded in high-complexity reads, per kilobase
+    #[arg(long, default_value_t = 0.0)]
+    tandem_repeats: f64,
+
+    /// Runs of N embedded in high-complexity reads, per kilobase
+    #[arg(long, default_value_t = 0.0)]
+    n_runs: f64,
+
+    /// Length range of embedded homopolymers and tandem repeats (MIN-MAX)
+    #[arg(long, default_value = "20-60", value_parser = parse_length_range)]
+    feature_length: (usize, usize),
+
+    /// Length range of embedded runs of N (MIN-MAX)
+    #[arg(long, default_value = "1-10", value_parser = parse_length_range)]
+    n_length: (usize, usize),
+
+    /// Write FASTA instead of FASTQ (the default for .fa, .fasta and .fna outputs)
+    #[arg(long)]
+    fasta: bool,
+
+    /// Write the low-complexity regions of each read as BED (read, start, end, category)
+    #[arg(long)]
+    truth: Option<String>,
+
+    /// Random seed
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+}
+
+/// Read length distribution of simulated reads
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LengthDistribution {
+    /// Within ±10% of the read length
+    Uniform,
+    /// All reads of the read length (Illumina-like)
+    Illumina,
+    /// Log-normal around the read length, with a long tail (ONT-like)
+    Ont,
+}
+
+impl From<LengthDistribution> for LengthModel {
+    fn from(distribution: LengthDistribution) -> Self {
+        match distribution {
+            LengthDistribution::Uniform => LengthModel::Uniform,
+            LengthDistribution::Illumina => LengthModel::Fixed,
+            LengthDistribution::Ont => LengthModel::LogNormal,
         }
     }
+}
 
-    // Process remaining records
-    if !chunk.is_empty() {
-        process_and_write_chunk(&mut chunk, &mut writer, &args)?;
+#[derive(ClapArgs, Debug, Clone)]
+struct BenchArgs {
+    /// Input file to mask (default: simulated reads)
+    #[arg(short = 'i', long)]
+    input: Option<String>,
+
+    /// Output TSV with one row per configuration (default: stdout)
+    #[arg(short = 'o', long)]
+    output: Option<String>,
+
+    /// Number of simulated reads
+    #[arg(short = 'n', long, default_value_t = 10000)]
+    num_reads: usize,
+
+    /// Comma-separated average lengths of simulated reads
+    #[arg(short = 'l', long, alias = "read-length", value_delimiter = ',', default_value = "1000")]
+    read_lengths: Vec<usize>,
+
+    /// Comma-separated window sizes
+    #[arg(short = 'w', long, value_delimiter = ',', default_value = "80")]
+    windows: Vec<usize>,
+
+    /// Comma-separated k-mer sizes (maximum k=15)
+    #[arg(short = 'k', long, value_delimiter = ',', default_value = "5")]
+    kmers: Vec<usize>,
+
+    /// Comma-separated methods for entropy calculation
+    #[arg(short = 'm', long, value_enum, value_delimiter = ',', default_value = "array,hashmap")]
+    methods: Vec<Method>,
+
+    /// Comma-separated thread counts (default: number of CPU cores)
+    #[arg(short = 'j', long, value_delimiter = ',')]
+    threads: Vec<usize>,
+
+    /// Entropy threshold
+    #[arg(short = 't', long, default_value_t = 0.70)]
+    threshold: f64,
+
+    /// Number of timed runs of each configuration (the fastest is reported)
+    #[arg(long, default_value_t = 3)]
+    iterations: usize,
+
+    /// Random seed of the simulated reads
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// Previous benchmark table to compare throughput with
+    #[arg(long)]
+    baseline: Option<String>,
+
+    /// With --baseline: exit with status 1 if a configuration is slower than the baseline by
+    /// more than this fraction (e.g. 0.2)
+    #[arg(long, requires = "baseline")]
+    max_slowdown: Option<f64>,
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+struct CalibrateArgs {
+    /// Input FASTQ, SAM or BAM file (plain or compressed, auto-detected). If not specified,
+    /// reads from stdin
+    #[arg(short = 'i', long)]
+    input: Option<String>,
+
+    /// Output TSV of masked bases and reads per window, k and threshold (default: stdout)
+    #[arg(short = 'o', long)]
+    output: Option<String>,
+
+    /// Write a TSV histogram of window entropies per window and k to this file
+    #[arg(long)]
+    histogram: Option<String>,
+
+    /// Number of reads to sample from the start of the input (0 = all reads)
+    #[arg(long, default_value_t = 10000)]
+    sample_reads: usize,
+
+    /// Comma-separated window sizes
+    #[arg(short = 'w', long, value_delimiter = ',', default_value = "80")]
+    windows: Vec<usize>,
+
+    /// Comma-separated k-mer sizes (maximum k=8)
+    #[arg(short = 'k', long, value_delimiter = ',', default_value = "5")]
+    kmers: Vec<usize>,
+
+    /// Comma-separated thresholds (default: 0.05 to 0.95 in steps of 0.05)
+    #[arg(short = 't', long, value_delimiter = ',')]
+    thresholds: Vec<f64>,
+
+    /// Number of threads to use (default: auto-detect CPU cores)
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+}
+
+/// A single input record with all its data
+#[derive(Clone)]
+struct SeqRecord {
+    id: Vec<u8>,
+    seq: Vec<u8>,
+    /// Phred+33 qualities (empty for FASTA or SAM/BAM records without qualities)
+    qual: Vec<u8>,
+    /// Content of the FASTQ '+' line after the '+' (usually empty)
+    plus: Vec<u8>,
+    /// The original SAM/BAM record, written back with the masked SEQ and QUAL
+    alignment: Option<RecordBuf>,
+}
+
+impl SeqRecord {
+    /// Prefix the read ID (and the SAM/BAM read name) with a file label and a ':'
+    fn add_label(&mut self, label: &str) {
+        let mut id = format!("{}:", label).into_bytes();
+        id.extend_from_slice(&self.id);
+        self.id = id;
+        if let Some(alignment) = self.alignment.as_mut() {
+            *alignment.name_mut() = Some(self.id.clone().into());
+        }
     }
+}
 
-    writer.flush()?;
-    Ok(())
+/// Reader for any of the supported input formats
+enum RecordReader {
+    Fastx(Box<dyn FastxReader>),
+    Sam(sam::io::Reader<Box<dyn BufRead>>, sam::Header),
+    Bam(bam::io::Reader<Box<dyn Read + Send>>, sam::Header),
 }
 
-/// Process a chunk of reads in parallel and write results
-fn process_and_write_chunk(
-    chunk: &mut Vec<FastqRecord>,
-    writer: &mut Box<dyn Write>,
-    args: &Args,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Process chunk in parallel using selected method
-    let results: Vec<(Vec<u8>, Vec<u8>)> = chunk
-        .par_iter()
-        .map(|record| {
-            match args.method {
-                Method::Auto => mask_sequence_auto(
-                    &record.seq,
-                    &record.qual,
-                    args.window,
-                    args.threshold,
-                    args.kmer,
-                ),
-                Method::Array => mask_sequence_array(
-                    &record.seq,
-                    &record.qual,
-                    args.window,
-                    args.threshold,
-                    args.kmer,
-                ),
-                Method::Hashmap => mask_sequence(
-                    &record.seq,
-                    &record.qual,
-                    args.window,
-                    args.threshold,
-                    args.kmer,
-                ),
+impl RecordReader {
+    /// Open a file or stdin, detecting the compression and format from its first bytes
+    ///
+    /// Compressed input is decompressed ahead of the parser on a separate thread, with
+    /// BGZF blocks and gzip members inflated in parallel.
+    fn open(path: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::open_counted(path, None)
+    }
+
+    /// Open a file or stdin, adding the bytes read (before decompression) to `count`
+    fn open_counted(path: Option<&str>, count: Option<Arc<AtomicU64>>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut inner: Box<dyn Read + Send> = match path {
+            Some(path) => Box::new(File::open(path)?),
+            None => Box::new(io::stdin()),
+        };
+        let read = Arc::new(AtomicU64::new(0));
+        inner = Box::new(CountingReader::new(inner, Arc::clone(&read)));
+        let mut count = count;
+        let mut head = read_head(&mut inner)?;
+        let compression = Compression::detect(&head);
+        if compression != Compression::None {
+            // Compressed bytes are counted behind the data the parser has consumed, not as
+            // the decompression threads read ahead
+            let position = InputPosition { read, consumed: count.take().unwrap_or_default() };
+            let bgzf = is_bgzf(&head);
+            let compressed = io::Cursor::new(head).chain(inner);
+            inner = if bgzf {
+                Box::new(parallel_bgzf_reader(compressed, position))
+            } else if compression == Compression::Gzip {
+                Box::new(parallel_gzip_reader(compressed, position))
+            } else {
+                Box::new(threaded_reader(decompress_reader(compression, Box::new(compressed))?, position))
+            };
+            head = read_head(&mut inner)?;
+        }
+        let format = SequenceFormat::detect(&head);
+        let mut input: Box<dyn Read + Send> = Box::new(io::Cursor::new(head).chain(inner));
+        if let Some(count) = count {
+            // Uncompressed input is counted as the parser reads it
+            input = Box::new(CountingReader::new(input, count));
+        }
+
+        Ok(match format {
+            SequenceFormat::Fastx => RecordReader::Fastx(parse_fastx_reader(input)?),
+            SequenceFormat::Sam => {
+                let mut reader = sam::io::Reader::new(Box::new(BufReader::new(input)) as Box<dyn BufRead>);
+                let header = reader.read_header()?;
+                RecordReader::Sam(reader, header)
+            }
+            SequenceFormat::Bam => {
+                let mut reader = bam::io::Reader::from(Box::new(BufReader::new(input)) as Box<dyn Read + Send>);
+                let header = reader.read_header()?;
+                RecordReader::Bam(reader, header)
             }
         })
-        .collect();
+    }
 
-    // Write results in order (sequential to preserve order)
-    for (i, (masked_seq, masked_qual)) in results.iter().enumerate() {
-        writeln!(writer, "@{}", String::from_utf8_lossy(&chunk[i].id))?;
-        writeln!(writer, "{}", String::from_utf8_lossy(masked_seq))?;
-        writeln!(writer, "+")?;
-        writeln!(writer, "{}", String::from_utf8_lossy(masked_qual))?;
+    fn format(&self) -> SequenceFormat {
+        match self {
+            RecordReader::Fastx(_) => SequenceFormat::Fastx,
+            RecordReader::Sam(..) => SequenceFormat::Sam,
+            RecordReader::Bam(..) => SequenceFormat::Bam,
+        }
     }
 
-    Ok(())
+    /// SAM header of SAM/BAM input
+    fn header(&self) -> Option<&sam::Header> {
+        match self {
+            RecordReader::Fastx(_) => None,
+            RecordReader::Sam(_, header) | RecordReader::Bam(_, header) => Some(header),
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<SeqRecord>, Box<dyn std::error::Error>> {
+        let mut alignment = RecordBuf::default();
+        let n = match self {
+            RecordReader::Fastx(reader) => {
+                return match reader.next() {
+                    Some(record) => {
+                        let rec = record?;
+                        Ok(Some(SeqRecord {
+                            id: rec.id().to_vec(),
+                            seq: rec.seq().to_vec(),
+                            qual: rec.qual().unwrap_or(&[]).to_vec(),
+                            plus: match rec.format() {
+                                Format::Fastq => plus_line(rec.all()).to_vec(),
+                                Format::Fasta => Vec::new(),
+                            },
+                            alignment: None,
+                        }))
+                    }
+                    None => Ok(None),
+                };
+            }
+            RecordReader::Sam(reader, header) => reader.read_record_buf(header, &mut alignment)?,
+            RecordReader::Bam(reader, header) => reader.read_record_buf(header, &mut alignment)?,
+        };
+        if n == 0 {
+            return Ok(None);
+        }
+        let (seq, qual) = sequence_and_quality(&alignment);
+        Ok(Some(SeqRecord {
+            id: read_name(&alignment),
+            seq,
+            qual,
+            plus: Vec::new(),
+            alignment: Some(alignment),
+        }))
+    }
+}
+
+/// Writer for any of the supported output formats
+enum RecordWriter {
+    /// FASTQ writer, and whether to reproduce the '+' line content
+    Fastq(Box<dyn FinishWrite>, bool),
+    Sam(sam::io::Writer<Box<dyn FinishWrite>>, sam::Header),
+    Bam(bam::io::Writer<BgzfWriter<Box<dyn FinishWrite>>>, sam::Header),
+    /// Masked intervals in a dustmasker format
+    Dust(Box<dyn FinishWrite>, DustFormat),
+}
+
+impl RecordWriter {
+    fn write_record(&mut self, record: &SeqRecord, result: &MaskedRead) -> io::Result<()> {
+        let (writer, header): (&mut dyn AlignmentWrite, &sam::Header) = match self {
+            RecordWriter::Fastq(writer, keep_plus) => {
+                let plus: &[u8] = if *keep_plus { &record.plus } else { b"" };
+                if result.header_tags.is_empty() {
+                    return write_fastq(writer, &record.id, &result.seq, plus, &result.qual);
+                }
+                let id = append_tags(&record.id, &result.header_tags);
+                return write_fastq(writer, &id, &result.seq, plus, &result.qual);
+            }
+            RecordWriter::Sam(writer, header) => (writer, header),
+            RecordWriter::Bam(writer, header) => (writer, header),
+            RecordWriter::Dust(writer, format) => {
+                let intervals: Vec<Interval> = result.intervals.iter().map(|li| li.interval).collect();
+                return format.write_record(writer, &record.id, &record.seq, &intervals);
+            }
+        };
+        // SAM/BAM output is only possible for SAM/BAM input
+        let alignment = result.alignment.as_ref().expect("SAM/BAM output requires SAM/BAM input");
+        writer.write_alignment_record(header, alignment)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            RecordWriter::Fastq(mut writer, _) | RecordWriter::Dust(mut writer, _) => writer.finish(),
+            RecordWriter::Sam(writer, _) => writer.into_inner().finish(),
+            RecordWriter::Bam(writer, _) => {
+                let mut bgzf_writer = writer.into_inner();
+                bgzf_writer.finish()?;
+                bgzf_writer.get_mut().finish()
+            }
+        }
+    }
+}
+
+/// Masking outcome of a read, for routing to separate outputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MaskOutcome {
+    /// Written unchanged
+    Clean,
+    /// Masked or trimmed, with unmasked bases left
+    Partial,
+    /// Masked or trimmed with no unmasked bases left
+    Full,
+}
+
+impl MaskOutcome {
+    fn of(record: &SeqRecord, result: &MaskedRead) -> Self {
+        if result.intervals.is_empty() && result.seq.len() == record.seq.len() {
+            MaskOutcome::Clean
+        } else if result.seq.iter().all(|&b| b == b'N') {
+            MaskOutcome::Full
+        } else {
+            MaskOutcome::Partial
+        }
+    }
+}
+
+/// The main output, optional separate outputs for each masking outcome and the entropy track
+struct OutputStreams {
+    main: RecordWriter,
+    clean: Option<RecordWriter>,
+    partial: Option<RecordWriter>,
+    full: Option<RecordWriter>,
+    /// bedGraph entropy track, and the window size and step
+    entropy: Option<(Box<dyn FinishWrite>, usize, usize)>,
+}
+
+impl OutputStreams {
+    /// Write a read to the output for its outcome, or the main output if there is none
+    fn write_record(&mut self, record: &SeqRecord, result: &MaskedRead) -> io::Result<()> {
+        if let Some((writer, window, step)) = self.entropy.as_mut() {
+            write_bedgraph(writer, read_id(&record.id), &result.entropy_track, *window, *step)?;
+        }
+        let writer = match MaskOutcome::of(record, result) {
+            MaskOutcome::Clean => self.clean.as_mut(),
+            MaskOutcome::Partial => self.partial.as_mut(),
+            MaskOutcome::Full => self.full.as_mut(),
+        };
+        writer.unwrap_or(&mut self.main).write_record(record, result)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        for writer in [self.clean, self.partial, self.full].into_iter().flatten() {
+            writer.finish()?;
+        }
+        if let Some((mut writer, _, _)) = self.entropy {
+            writer.finish()?;
+        }
+        self.main.finish()
+    }
+}
+
+/// Output compression: --output-format, then the output extension, then gzip if a level is given.
+/// BAM is always BGZF-compressed, so this only applies to text output.
+fn compression_for(
+    path: Option<&str>,
+    format: SequenceFormat,
+    output_format: Option<&OutputFormat>,
+    level: Option<u32>,
+) -> Compression {
+    match (output_format, level) {
+        _ if format == SequenceFormat::Bam => Compression::None,
+        (_, Some(0)) => Compression::None,
+        (Some(output_format), _) => Compression::from(output_format),
+        (None, level) => path
+            .and_then(Compression::from_path)
+            .or(level.map(|_| Compression::Gzip))
+            .unwrap_or(Compression::None),
+    }
+}
+
+/// Create a record writer to a file or stdout
+fn open_writer(
+    path: Option<&str>,
+    format: SequenceFormat,
+    compression: Compression,
+    level: Option<u32>,
+    gzi: bool,
+    header: Option<&sam::Header>,
+    args: &Args,
+) -> io::Result<RecordWriter> {
+    if args.discard_output {
+        return Ok(RecordWriter::Fastq(compress_writer(io::sink(), Compression::None, 1, None)?, false));
+    }
+
+    // Default to level 1 (fast compression) when compressing by extension or --output-format
+    let text_level = level.unwrap_or(1);
+    let writer: Box<dyn FinishWrite> = if let Some(path) = path {
+        if gzi {
+            let writer = BgzfWriter::new(File::create(path)?, text_level);
+            Box::new(writer.with_index(format!("{}.gzi", path)))
+        } else {
+            compress_writer(File::create(path)?, compression, text_level, args.threads)?
+        }
+    } else {
+        compress_writer(io::stdout(), compression, text_level, args.threads)?
+    };
+
+    if let Some(outfmt) = args.outfmt {
+        return Ok(RecordWriter::Dust(writer, DustFormat::from(outfmt)));
+    }
+    Ok(match (format, header) {
+        (SequenceFormat::Sam, Some(header)) => {
+            let mut writer = sam::io::Writer::new(writer);
+            writer.write_header(header)?;
+            RecordWriter::Sam(writer, header.clone())
+        }
+        (SequenceFormat::Bam, Some(header)) => {
+            // BAM defaults to level 6, like samtools
+            let bgzf_writer = BgzfWriter::new(writer, level.unwrap_or(6));
+            let mut writer = bam::io::Writer::from(bgzf_writer);
+            writer.write_header(header)?;
+            RecordWriter::Bam(writer, header.clone())
+        }
+        _ => RecordWriter::Fastq(writer, args.keep_plus),
+    })
+}
+
+fn main() -> ExitCode {
+    let argv = match command_line() {
+        Ok(argv) => argv,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let cli = match parse_cli(argv) {
+        Ok(cli) => cli,
+        Err(e) => {
+            // Usage errors, --help and --version
+            let _ = e.print();
+            return ExitCode::from(e.exit_code() as u8);
+        }
+    };
+    // As diff and cmp: 1 if the masking differs, 2 if the comparison failed
+    if let Some(Command::Compare(args)) = &cli.command {
+        return compare(args).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            ExitCode::from(2)
+        });
+    }
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run the masking or a subcommand other than compare
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match &cli.command {
+        Some(Command::Simulate(args)) => return simulate(args),
+        Some(Command::Bench(args)) => return bench(args),
+        Some(Command::Calibrate(args)) => return calibrate(args),
+        _ => {}
+    }
+    if let Some(Command::Evaluate(args)) = &cli.command {
+        return evaluate(args);
+    }
+    let args = cli.into_mask_args().expect("masking subcommand");
+    set_verbosity(&args);
+    if args.discard_output {
+        reject_read_outputs(&args, "stats")?;
+    }
+
+    // Options of every file are checked before any file is processed
+    let jobs = batch_jobs(&args)?;
+    let runs: Vec<(Args, MaskPipeline, Option<TailConfig>)> = match &jobs {
+        Some(jobs) => jobs
+            .iter()
+            .map(|job_args| {
+                let (pipeline, tail_config) =
+                    configure(job_args).map_err(|e| format!("{}: {}", job_args.input[0], e))?;
+                Ok((job_args.clone(), pipeline, tail_config))
+            })
+            .collect::<Result<_, Box<dyn std::error::Error>>>()?,
+        None => {
+            let (pipeline, tail_config) = configure(&args)?;
+            vec![(args.clone(), pipeline, tail_config)]
+        }
+    };
+
+    // Check if stdin is a terminal and no input file specified
+    if jobs.is_none() && args.input.is_empty() && std::io::stdin().is_terminal() {
+        return Err(concat!(
+            "No input provided. Use -i to specify input file or pipe data to stdin.\n",
+            "\n",
+            "Usage:\n",
+            "  rustmasker mask -i input.fastq[.gz] -o output.fastq [OPTIONS]\n",
+            "  cat input.fastq[.gz] | rustmasker mask [OPTIONS] > output.fastq\n",
+            "\n",
+            "Note: Input can be FASTQ, SAM or BAM, plain or gzip/zstd/bzip2/xz compressed (auto-detected)\n",
+            "\n",
+            "Compression:\n",
+            "  - stdout: uncompressed by default (use -c 1-9 to compress)\n",
+            "  - .gz/.zst/.bz2/.xz files: compressed at level 1 by default (use -c to override)\n",
+            "  - other files: uncompressed (use -c 1-9 to compress)\n",
+            "\n",
+            "Examples:\n",
+            "  rustmasker mask -i reads.fastq.gz -o masked.fastq -j 4         # uncompressed\n",
+            "  rustmasker mask -i reads.fastq.gz -o masked.fastq.gz -j 4      # compressed (level 1)\n",
+            "  rustmasker mask -i reads.fastq.gz -o masked.fastq.gz -c 6 -j 4 # compressed (level 6)\n",
+            "  cat reads.fastq | rustmasker mask -j 4 > masked.fastq         # uncompressed stdout\n",
+            "\n",
+            "For full help, use: rustmasker mask --help (other subcommands: rustmasker --help)"
+        )
+        .into());
+    }
+
+    // Configure thread pool if specified
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+
+    if jobs.is_none() {
+        let (args, pipeline, tail_config) = &runs[0];
+        let files = mask_file(args, pipeline, tail_config.as_ref())?;
+        if let Some(stats_path) = &args.stats {
+            // Concatenated inputs keep statistics per input file
+            if args.concat {
+                write_files_json(stats_path, &files)?;
+            } else {
+                files[0].stats.write_json(stats_path)?;
+            }
+        } else if args.discard_output {
+            // `rustmasker stats` prints the statistics instead of the reads
+            let json = if args.concat { files_json(&files) } else { files[0].stats.to_json() };
+            println!("{}", serde_json::to_string_pretty(&json)?);
+        }
+        return Ok(());
+    }
+
+    // Several inputs share the thread pool; statistics are kept per file
+    let mut files = Vec::new();
+    for (job_args, pipeline, tail_config) in &runs {
+        let input = job_args.input[0].clone();
+        let mut file =
+            mask_file(job_args, pipeline, tail_config.as_ref()).map_err(|e| format!("{}: {}", input, e))?;
+        files.append(&mut file);
+    }
+    if let Some(stats_path) = &args.stats {
+        write_files_json(stats_path, &files)?;
+    }
+    Ok(())
+}
+
+/// Sample reads and write the masked fraction at each window, k and threshold
+fn calibrate(args: &CalibrateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    for &k in &args.kmers {
+        if !(1..=8).contains(&k) {
+            return Err(format!("calibrate supports k-mer sizes from 1 to 8 (got {})", k).into());
+        }
+        if let Some(&window) = args.windows.iter().find(|&&window| window <= k) {
+            return Err(format!("window size {} must be larger than k={}", window, k).into());
+        }
+    }
+    if args.thresholds.iter().any(|t| !(0.0..=1.0).contains(t)) {
+        return Err("thresholds must be in range [0, 1]".into());
+    }
+    let thresholds = if args.thresholds.is_empty() {
+        threshold_sweep(0.05, 0.95, 0.05)
+    } else {
+        args.thresholds.clone()
+    };
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+
+    let mut reader = RecordReader::open(args.input.as_deref())?;
+    let mut sample = Vec::new();
+    while let Some(record) = reader.next_record()? {
+        sample.push(record.seq);
+        if sample.len() == args.sample_reads {
+            break;
+        }
+    }
+
+    let mut calibrations = Vec::new();
+    for &window in &args.windows {
+        for &k in &args.kmers {
+            let new = || Calibration::new(window, k, &thresholds);
+            let calibration = sample
+                .par_iter()
+                .fold(new, |mut calibration, seq| {
+                    calibration.add_sequence(seq);
+                    calibration
+                })
+                .reduce(new, |mut a, b| {
+                    a.merge(&b);
+                    a
+                });
+            calibrations.push(calibration);
+        }
+    }
+
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    writeln!(output, "window\tk\tthreshold\tmasked_bases\tbases\tmasked_fraction\tmasked_reads\treads")?;
+    for c in &calibrations {
+        for (i, threshold) in c.thresholds.iter().enumerate() {
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}\t{}\t{:.6}\t{}\t{}",
+                c.window,
+                c.k,
+                threshold,
+                c.masked_bases[i],
+                c.bases,
+                c.masked_fraction(i),
+                c.masked_reads[i],
+                c.reads
+            )?;
+        }
+    }
+    output.flush()?;
+
+    if let Some(path) = &args.histogram {
+        let mut histogram = BufWriter::new(File::create(path)?);
+        writeln!(histogram, "window\tk\tentropy_min\tentropy_max\twindows")?;
+        for c in &calibrations {
+            for (bin, count) in c.histogram.iter().enumerate() {
+                writeln!(
+                    histogram,
+                    "{}\t{}\t{:.2}\t{:.2}\t{}",
+                    c.window,
+                    c.k,
+                    bin as f64 / HISTOGRAM_BINS as f64,
+                    (bin + 1) as f64 / HISTOGRAM_BINS as f64,
+                    count
+                )?;
+            }
+        }
+        histogram.flush()?;
+    }
+    Ok(())
+}
+
+/// Compare the masked bases of two files read by read: exit status 0 if they match, 1 if the
+/// masking differs (errors, returned to main, exit with 2)
+fn compare(args: &CompareArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let mut first = RecordReader::open(Some(&args.first))?;
+    let mut second = RecordReader::open(Some(&args.second))?;
+    let mut original = args.original.as_deref().map(|path| RecordReader::open(Some(path))).transpose()?;
+    let mut diffs = args.diffs.as_deref().map(|path| create_output(Some(path))).transpose()?;
+    let mut extract = args.extract.as_deref().map(|path| create_output(Some(path))).transpose()?;
+    if let Some(diffs) = &mut diffs {
+        writeln!(
+            diffs,
+            "read_id\tlength\tclass\tmasked_first\tmasked_second\tonly_first\tonly_second\tintervals_first\tintervals_second"
+        )?;
+    }
+
+    let mut comparison = Comparison::default();
+    let mut examples = Vec::new();
+    loop {
+        let (a, b) = match (first.next_record()?, second.next_record()?) {
+            (Some(a), Some(b)) => (a, b),
+            (None, None) => break,
+            _ => return Err(format!("{} and {} have different numbers of reads", args.first, args.second).into()),
+        };
+        if read_id(&a.id) != read_id(&b.id) || a.seq.len() != b.seq.len() {
+            return Err(format!(
+                "read {} differs in ID or length ({} vs {})",
+                comparison.reads + 1,
+                String::from_utf8_lossy(read_id(&a.id)),
+                String::from_utf8_lossy(read_id(&b.id))
+            )
+            .into());
+        }
+        let unmasked = match &mut original {
+            Some(reader) => match reader.next_record()? {
+                Some(record) if read_id(&record.id) == read_id(&a.id) && record.seq.len() == a.seq.len() => {
+                    Some(record)
+                }
+                _ => {
+                    return Err(format!(
+                        "--original does not have read {} ({})",
+                        comparison.reads + 1,
+                        String::from_utf8_lossy(read_id(&a.id))
+                    )
+                    .into());
+                }
+            },
+            None => None,
+        };
+
+        let diff = diff_read(&a.seq, &b.seq, unmasked.as_ref().map(|r| r.seq.as_slice()), args.window);
+        comparison.add(a.seq.len(), &diff);
+        let Some(class) = diff.class else { continue };
+
+        if let Some(diffs) = &mut diffs {
+            diffs.write_all(read_id(&a.id))?;
+            writeln!(
+                diffs,
+                "\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                a.seq.len(),
+                class.name(),
+                diff.masked_first,
+                diff.masked_second,
+                diff.only_first,
+                diff.only_second,
+                format_runs(&n_runs(&a.seq)),
+                format_runs(&n_runs(&b.seq))
+            )?;
+        }
+        if let Some(extract) = &mut extract {
+            let record = unmasked.as_ref().unwrap_or(&a);
+            write_fastq(extract, &record.id, &record.seq, b"", &record.qual)?;
+        }
+        if examples.len() < COMPARE_EXAMPLES {
+            examples.push((a, b.seq, diff));
+        }
+    }
+    if let Some(mut diffs) = diffs {
+        diffs.finish()?;
+    }
+    if let Some(mut extract) = extract {
+        extract.finish()?;
+    }
+
+    if !args.quiet {
+        for (record, second_seq, diff) in &examples {
+            let len = record.seq.len();
+            println!("{}", String::from_utf8_lossy(read_id(&record.id)));
+            println!("  length {}, class {}", len, diff.class.map_or("", |c| c.name()));
+            println!("  masked: first {}, second {}", diff.masked_first, diff.masked_second);
+            let ends = |seq: &[u8]| {
+                if len <= 2 * COMPARE_EXAMPLE_BASES {
+                    String::from_utf8_lossy(seq).into_owned()
+                } else {
+                    format!(
+                        "{}...{}",
+                        String::from_utf8_lossy(&seq[..COMPARE_EXAMPLE_BASES]),
+                        String::from_utf8_lossy(&seq[len - COMPARE_EXAMPLE_BASES..])
+                    )
+                }
+            };
+            println!("  first:  {}", ends(&record.seq));
+            println!("  second: {}", ends(second_seq));
+        }
+        if !examples.is_empty() {
+            println!();
+        }
+    }
+
+    println!("reads\t{}", comparison.reads);
+    println!("bases\t{}", comparison.bases);
+    println!("differing_reads\t{}", comparison.differing_reads);
+    println!("masked_only_in_first\t{}", comparison.only_first_bases);
+    println!("masked_only_in_second\t{}", comparison.only_second_bases);
+    println!("first_masks_more\t{}", comparison.first_masks_more);
+    println!("second_masks_more\t{}", comparison.second_masks_more);
+    for (class, count) in DiffClass::ALL.iter().zip(comparison.classes) {
+        println!("{}\t{}", class.name(), count);
+    }
+    Ok(if comparison.is_match() { ExitCode::SUCCESS } else { ExitCode::from(1) })
+}
+
+/// Number of differing reads `compare` prints as examples
+const COMPARE_EXAMPLES: usize = 5;
+/// Bases shown from each end of an example read
+const COMPARE_EXAMPLE_BASES: usize = 50;
+
+/// Intervals as `start-end,...` (half-open), or `-` if there are none
+fn format_runs(runs: &[Interval]) -> String {
+    if runs.is_empty() {
+        return "-".to_string();
+    }
+    runs.iter().map(|run| format!("{}-{}", run.start, run.end)).collect::<Vec<_>>().join(",")
+}
+
+/// Open an output file compressed according to its extension, or stdout
+fn create_output(path: Option<&str>) -> io::Result<Box<dyn FinishWrite>> {
+    match path {
+        Some(path) => {
+            let compression = Compression::from_path(path).unwrap_or(Compression::None);
+            compress_writer(File::create(path)?, compression, 1, None)
+        }
+        None => compress_writer(io::stdout(), Compression::None, 1, None),
+    }
+}
+
+/// Fail if options that write reads are given to a subcommand that does not
+fn reject_read_outputs(args: &Args, subcommand: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if args.output.is_some()
+        || args.output_dir.is_some()
+        || args.manifest.is_some()
+        || args.outfmt.is_some()
+        || args.clean_output.is_some()
+        || args.partial_output.is_some()
+        || args.full_output.is_some()
+    {
+        return Err(format!(
+            "{} does not write reads (remove -o, --output-dir, --manifest, --outfmt and separate outputs)",
+            subcommand
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Mask reads and score the masked intervals against a truth BED
+fn evaluate(args: &EvaluateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mask = &args.mask;
+    set_verbosity(mask);
+    reject_read_outputs(mask, "evaluate")?;
+    if mask.poly_x == PolyXMode::Trim {
+        return Err("evaluate cannot score trimmed reads (use --poly-x mask)".into());
+    }
+    if !(0.0..=1.0).contains(&args.min_overlap) {
+        return Err("--min-overlap must be in range [0, 1]".into());
+    }
+    let text = std::fs::read_to_string(&args.truth)
+        .map_err(|e| format!("cannot read truth BED {}: {}", args.truth, e))?;
+    let truth = parse_truth_bed(&text).map_err(|e| format!("{}: {}", args.truth, e))?;
+    let (pipeline, _) = configure(mask)?;
+
+    if let Some(threads) = mask.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+
+    let mut evaluation = Evaluation::new(args.min_overlap);
+    let mut unseen: HashSet<&str> = truth.keys().map(String::as_str).collect();
+    let inputs: Vec<Option<&str>> = if mask.input.is_empty() {
+        vec![None]
+    } else {
+        mask.input.iter().map(|input| Some(input.as_str())).collect()
+    };
+    for input in inputs {
+        let (first_read, mut bases, mut predicted_bases) = (evaluation.reads, 0, 0);
+        let progress = input_progress(input, mask);
+        let mut reader = RecordReader::open_counted(input, Some(progress.counter()))?;
+        let mut chunk = Vec::with_capacity(mask.chunk_size);
+        loop {
+            let record = reader.next_record()?;
+            let done = record.is_none();
+            if let Some(record) = &record {
+                progress.record_read(record.seq.len());
+            }
+            chunk.extend(record);
+            if chunk.len() < mask.chunk_size && !done {
+                continue;
+            }
+            let predicted: Vec<Vec<Interval>> = chunk
+                .par_iter()
+                .map(|record: &SeqRecord| pipeline.run(&record.seq).iter().map(|li| li.interval).collect())
+                .collect();
+            for (record, predicted) in chunk.iter().zip(&predicted) {
+                let name = String::from_utf8_lossy(read_id(&record.id));
+                let read_truth = truth.get(name.as_ref()).map_or(&[][..], Vec::as_slice);
+                unseen.remove(name.as_ref());
+                evaluation.add_read(read_truth, predicted);
+                bases += record.seq.len() as u64;
+                predicted_bases += predicted.iter().map(|iv| iv.len() as u64).sum::<u64>();
+            }
+            chunk.clear();
+            progress.record_masked(bases, predicted_bases);
+            if done {
+                break;
+            }
+        }
+        report_done(&progress, mask, evaluation.reads - first_read, bases, predicted_bases);
+    }
+    if !unseen.is_empty() {
+        warning!("{} reads of the truth BED were not in the input", unseen.len());
+    }
+
+    let mut output = BufWriter::new(io::stdout());
+    writeln!(
+        output,
+        "category\ttruth_intervals\tdetected_intervals\tinterval_sensitivity\ttruth_bases\tdetected_bases\tbase_sensitivity\tpredicted_intervals\tinterval_precision\tpredicted_bases\tbase_precision"
+    )?;
+    let rows = evaluation.categories.iter().map(|(category, counts)| (category.as_str(), counts));
+    for (category, counts) in rows.chain(std::iter::once(("all", &evaluation.total))) {
+        write!(
+            output,
+            "{}\t{}\t{}\t{:.4}\t{}\t{}\t{:.4}",
+            category,
+            counts.truth_intervals,
+            counts.detected_intervals,
+            counts.interval_sensitivity(),
+            counts.truth_bases,
+            counts.detected_bases,
+            counts.base_sensitivity()
+        )?;
+        // Predicted intervals are not attributed to categories
+        if category == "all" {
+            writeln!(
+                output,
+                "\t{}\t{:.4}\t{}\t{:.4}",
+                evaluation.predicted_intervals,
+                evaluation.interval_precision(),
+                evaluation.predicted_bases,
+                evaluation.base_precision()
+            )?;
+        } else {
+            writeln!(output, "\tNA\tNA\tNA\tNA")?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Write synthetic reads, as scripts/generate_test_data.py, and optionally their truth BED
+fn simulate(args: &SimulateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if args.low_complexity < 0.0
+        || args.medium_complexity < 0.0
+        || args.low_complexity + args.medium_complexity > 1.0
+    {
+        return Err("complexity fractions must be non-negative and sum to at most 1".into());
+    }
+    if args.homopolymers < 0.0 || args.tandem_repeats < 0.0 || args.n_runs < 0.0 {
+        return Err("embedded feature rates must be non-negative".into());
+    }
+    let embedding = Embedding {
+        homopolymers: args.homopolymers,
+        tandem_repeats: args.tandem_repeats,
+        n_runs: args.n_runs,
+        min_length: args.feature_length.0,
+        max_length: args.feature_length.1,
+        min_n_length: args.n_length.0,
+        max_n_length: args.n_length.1,
+    };
+    let length_model = LengthModel::from(args.length_model);
+    let fasta = args.fasta || args.output.as_deref().is_some_and(SequenceFormat::is_fasta_path);
+
+    let mut rng = Rng::new(args.seed);
+    let mut output = create_output(args.output.as_deref())?;
+    let mut truth = args.truth.as_deref().map(|path| create_output(Some(path))).transpose()?;
+    let mix = complexity_mix(args.num_reads, args.low_complexity, args.medium_complexity);
+    for (read_number, complexity) in mix.into_iter().enumerate() {
+        let len = length_model.sample(&mut rng, args.read_length);
+        let read = simulate_read(&mut rng, complexity, len, &embedding);
+        if fasta {
+            writeln!(output, ">read_{}", read_number)?;
+            output.write_all(&read.seq)?;
+            writeln!(output)?;
+        } else {
+            // Q30 throughout
+            writeln!(output, "@read_{}", read_number)?;
+            output.write_all(&read.seq)?;
+            writeln!(output, "\n+\n{}", "?".repeat(len))?;
+        }
+        if let Some(truth) = &mut truth {
+            for (iv, feature) in &read.truth {
+                writeln!(truth, "read_{}\t{}\t{}\t{}", read_number, iv.start, iv.end, feature.name())?;
+            }
+        }
+    }
+    output.finish()?;
+    if let Some(mut truth) = truth {
+        truth.finish()?;
+    }
+    Ok(())
+}
+
+/// Time entropy masking for each method, k, window, read length and thread count
+fn bench(args: &BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    for &k in &args.kmers {
+        if k == 0 || k > 15 {
+            return Err(format!("k-mer size k={} is not supported (1 ≤ k ≤ 15)", k).into());
+        }
+        if let Some(&window) = args.windows.iter().find(|&&window| window <= k) {
+            return Err(format!("window size {} must be larger than k={}", window, k).into());
+        }
+    }
+    if args.threads.contains(&0) || args.read_lengths.contains(&0) {
+        return Err("thread counts and read lengths must be at least 1".into());
+    }
+    let baseline = match &args.baseline {
+        Some(path) => {
+            let text =
+                std::fs::read_to_string(path).map_err(|e| format!("cannot read baseline {}: {}", path, e))?;
+            Some(parse_bench_table(&text).map_err(|e| format!("{}: {}", path, e))?)
+        }
+        None => None,
+    };
+    let thread_counts = if args.threads.is_empty() {
+        vec![std::thread::available_parallelism().map_or(1, |n| n.get())]
+    } else {
+        args.threads.clone()
+    };
+
+    // Each read set is either the input file (labelled with its mean read length) or
+    // simulated reads of one of the given lengths
+    let read_sets: Vec<(usize, Vec<Vec<u8>>)> = match &args.input {
+        Some(path) => {
+            let mut reader = RecordReader::open(Some(path))?;
+            let mut reads = Vec::new();
+            while let Some(record) = reader.next_record()? {
+                reads.push(record.seq);
+            }
+            let bases: usize = reads.iter().map(Vec::len).sum();
+            vec![(bases / reads.len().max(1), reads)]
+        }
+        None => args
+            .read_lengths
+            .iter()
+            .map(|&read_length| {
+                let mut rng = Rng::new(args.seed);
+                let reads = complexity_mix(args.num_reads, 0.3, 0.3)
+                    .into_iter()
+                    .map(|complexity| {
+                        let len = LengthModel::Uniform.sample(&mut rng, read_length);
+                        sequence(&mut rng, complexity, len)
+                    })
+                    .collect();
+                (read_length, reads)
+            })
+            .collect(),
+    };
+
+    let mut output = create_output(args.output.as_deref())?;
+    write!(output, "{}", BENCH_COLUMNS)?;
+    if baseline.is_some() {
+        write!(output, "\tbaseline_megabases_per_second\tchange")?;
+    }
+    writeln!(output)?;
+
+    let mut slower = Vec::new();
+    for (read_length, reads) in &read_sets {
+        let bases: usize = reads.iter().map(Vec::len).sum();
+        for &threads in &thread_counts {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+            for method in &args.methods {
+                for &k in &args.kmers {
+                    for &window in &args.windows {
+                        let entropy_method = EntropyMethod::from(method);
+                        let mut best = f64::INFINITY;
+                        let mut masked_bases = 0;
+                        for _ in 0..args.iterations.max(1) {
+                            let start = Instant::now();
+                            masked_bases = pool.install(|| {
+                                reads
+                                    .par_iter()
+                                    .map(|seq| {
+                                        let intervals = entropy_mask_intervals(
+                                            seq,
+                                            window,
+                                            args.threshold,
+                                            k,
+                                            entropy_method,
+                                            ShortReadPolicy::WholeRead,
+                                        );
+                                        intervals.iter().map(Interval::len).sum::<usize>()
+                                    })
+                                    .sum()
+                            });
+                            best = best.min(start.elapsed().as_secs_f64());
+                        }
+
+                        let result = BenchResult {
+                            method: method.to_possible_value().unwrap().get_name().to_string(),
+                            k,
+                            window,
+                            read_length: *read_length,
+                            threads,
+                            reads: reads.len(),
+                            bases,
+                            masked_bases,
+                            seconds: best,
+                        };
+                        write!(output, "{}", result.row())?;
+                        if let Some(baseline) = &baseline {
+                            match baseline.get(&result.key()) {
+                                Some(&previous) => {
+                                    let change = result.megabases_per_second() / previous - 1.0;
+                                    write!(output, "\t{:.2}\t{:+.1}%", previous, change * 100.0)?;
+                                    if args.max_slowdown.is_some_and(|max| change < -max) {
+                                        slower.push(result.key());
+                                    }
+                                }
+                                None => write!(output, "\tNA\tNA")?,
+                            }
+                        }
+                        writeln!(output)?;
+                        output.flush()?;
+                    }
+                }
+            }
+        }
+    }
+    output.finish()?;
+
+    if !slower.is_empty() {
+        return Err(format!(
+            "{} configurations are slower than the baseline by more than {:.0}%: {}",
+            slower.len(),
+            args.max_slowdown.unwrap_or(0.0) * 100.0,
+            slower.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The command line, with the options of --preset and then --config inserted before the
+/// user's own options, which therefore override them
+fn command_line() -> Result<Vec<OsString>, Box<dyn std::error::Error>> {
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    // Errors are reported when the full command line is parsed
+    let Ok(mut cli) = Cli::try_parse_from(&argv) else {
+        return Ok(argv);
+    };
+    let at = if cli.command.is_some() { 2 } else { 1 };
+    let writes_reads = matches!(cli.command, None | Some(Command::Mask(_)));
+    let Some(args) = cli.mask_options_mut() else {
+        return Ok(argv);
+    };
+
+    let mut preset = args.preset;
+    let mut config_options = Vec::new();
+    if let Some(path) = &args.config {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read config {}: {}", path, e))?;
+        let options = parse_config(&text).map_err(|e| format!("{}: {}", path, e))?;
+        for (key, value) in options {
+            let result = match (key.as_str(), value) {
+                ("preset", ConfigValue::Values(values)) if values.len() == 1 => {
+                    Preset::from_str(&values[0], false).map(|p| {
+                        // A preset on the command line replaces that of the config file
+                        preset = preset.or(Some(p));
+                    })
+                }
+                (_, value) => config_option(&key, value).map(|option| config_options.extend(option)),
+            };
+            if let Err(e) = result {
+                return Err(format!("{}: {}", path, e).into());
+            }
+        }
+    }
+
+    let mut options = Vec::new();
+    if let Some(preset) = preset {
+        // Also recorded when it comes from the config file
+        options.push(format!("--preset={}", preset.to_possible_value().unwrap().get_name()).into());
+        options.extend(preset.options().iter().map(OsString::from));
+        if writes_reads {
+            options.extend(preset.output_options().iter().map(OsString::from));
+        }
+    }
+    options.extend(config_options);
+    argv.splice(at..at, options);
+    Ok(argv)
+}
+
+/// Command-line form of a config file option
+///
+/// Flags and options whose value can be left out (`progress = true`) take booleans; other
+/// options get `true` or `false` as their value.
+fn config_option(key: &str, value: ConfigValue) -> Result<Vec<OsString>, String> {
+    let command = Args::augment_args(clap::Command::new("rustmasker"));
+    let arg = command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(key) && key != "config")
+        .ok_or_else(|| format!("unknown option '{}'", key))?;
+    let flag = format!("--{}", key);
+    let takes_values = arg.get_action().takes_values();
+    let boolean = !takes_values || arg.get_num_args().is_some_and(|n| n.min_values() == 0);
+    Ok(match value {
+        ConfigValue::Flag(true) if boolean => vec![flag.into()],
+        // `false` switches off a flag set by a preset through its counterpart, if it has one
+        ConfigValue::Flag(false) if boolean => {
+            let negation =
+                key.strip_prefix("no-").map(String::from).unwrap_or_else(|| format!("no-{}", key));
+            if command.get_arguments().any(|arg| arg.get_long() == Some(negation.as_str())) {
+                vec![format!("--{}", negation).into()]
+            } else {
+                Vec::new()
+            }
+        }
+        ConfigValue::Values(_) if !takes_values => {
+            return Err(format!("option '{}' is a flag (use true or false)", key));
+        }
+        ConfigValue::Flag(set) => vec![format!("{}={}", flag, set).into()],
+        ConfigValue::Values(values) if values.len() == 1 || arg.get_value_delimiter().is_some() => {
+            vec![format!("{}={}", flag, values.join(",")).into()]
+        }
+        ConfigValue::Values(values) => std::iter::once(flag).chain(values).map(OsString::from).collect(),
+    })
+}
+
+/// Parse a command line, recording the effective masking options for the stats output
+fn parse_cli(argv: Vec<OsString>) -> Result<Cli, clap::Error> {
+    let matches = Cli::command().try_get_matches_from(&argv)?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    if let Some(args) = cli.mask_options_mut() {
+        args.argv = argv;
+        let command = Cli::command();
+        let (command, matches) = match matches.subcommand() {
+            Some((name, sub)) => (command.find_subcommand(name).expect("parsed subcommand").clone(), sub),
+            None => (command, &matches),
+        };
+        args.effective_config = Some(effective_config(&command, matches));
+    }
+    Ok(cli)
+}
+
+/// Values of all options of a command (including defaults) as JSON, keyed by option name
+fn effective_config(command: &clap::Command, matches: &ArgMatches) -> serde_json::Value {
+    let json_value = |raw: &std::ffi::OsStr| {
+        let raw = raw.to_string_lossy();
+        if let Ok(flag) = raw.parse::<bool>() {
+            serde_json::json!(flag)
+        } else if let Ok(n) = raw.parse::<i64>() {
+            serde_json::json!(n)
+        } else if let Ok(x) = raw.parse::<f64>() {
+            serde_json::json!(x)
+        } else {
+            serde_json::json!(raw)
+        }
+    };
+    let mut config = serde_json::Map::new();
+    for id in command.get_arguments().map(|arg| arg.get_id()) {
+        let Ok(Some(raw)) = matches.try_get_raw(id.as_str()) else {
+            continue;
+        };
+        let mut values: Vec<serde_json::Value> = raw.map(json_value).collect();
+        let value = if values.len() == 1 { values.remove(0) } else { values.into() };
+        config.insert(id.to_string(), value);
+    }
+    config.into()
+}
+
+/// Set the verbosity of warnings and other messages from -v and -q
+fn set_verbosity(args: &Args) {
+    let verbosity = if args.quiet { 0 } else { args.verbose.saturating_add(1) };
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+}
+
+/// Options that are set per run and cannot be changed for a single file of a manifest
+const RUN_OPTIONS: &[&str] = &[
+    "input", "output", "output_dir", "manifest", "stats", "report", "threads", "clean_output",
+    "partial_output", "full_output", "concat", "no_concat", "label_reads", "no_label_reads",
+    "labels", "entropy_bedgraph", "config", "preset", "verbose", "quiet",
+];
+
+/// Options for each file of a run with several inputs (multiple -i with --output-dir, or
+/// --manifest), or None for a single input
+fn batch_jobs(args: &Args) -> Result<Option<Vec<Args>>, Box<dyn std::error::Error>> {
+    if args.concat && (args.manifest.is_some() || args.output_dir.is_some()) {
+        return Err("--concat cannot be combined with --manifest or --output-dir".into());
+    }
+    if (args.label_reads || !args.labels.is_empty()) && !args.concat {
+        return Err("--label-reads and --labels require --concat".into());
+    }
+    if !args.labels.is_empty() && args.labels.len() != args.input.len() {
+        return Err(format!(
+            "--labels has {} labels for {} input files",
+            args.labels.len(),
+            args.input.len()
+        )
+        .into());
+    }
+
+    let jobs = if let Some(manifest) = &args.manifest {
+        if !args.input.is_empty() || args.output.is_some() || args.output_dir.is_some() {
+            return Err("--manifest cannot be combined with -i, -o or --output-dir".into());
+        }
+        let text = std::fs::read_to_string(manifest)
+            .map_err(|e| format!("cannot read manifest {}: {}", manifest, e))?;
+        parse_manifest(&text)
+    } else if let Some(dir) = &args.output_dir {
+        if args.output.is_some() || args.input.is_empty() {
+            return Err("--output-dir requires input files (-i) and cannot be combined with -o".into());
+        }
+        args.input.iter().map(|input| job_in_dir(input, dir)).collect()
+    } else {
+        if args.input.len() > 1 && !args.concat {
+            return Err("several input files require --output-dir, --manifest or --concat".into());
+        }
+        return Ok(None);
+    };
+    let jobs = jobs.and_then(|jobs| check_outputs(&jobs).map(|_| jobs))?;
+
+    if args.report.is_some()
+        || args.entropy_bedgraph.is_some()
+        || args.clean_output.is_some()
+        || args.partial_output.is_some()
+        || args.full_output.is_some()
+    {
+        return Err("--report, --entropy-bedgraph and separate outputs by outcome are not supported with several input files".into());
+    }
+
+    let jobs = jobs
+        .into_iter()
+        .map(|job| -> Result<Args, Box<dyn std::error::Error>> {
+            let mut job_args = if job.params.is_empty() {
+                args.clone()
+            } else {
+                // Only the first line of clap's message, without its usage
+                let manifest_error = |e: clap::Error| {
+                    let message = e.to_string();
+                    let message = message.lines().next().unwrap_or_default().trim_start_matches("error: ");
+                    format!("in the manifest options for {}: {}", job.input, message)
+                };
+                let command = Args::augment_args(clap::Command::new("rustmasker").args_override_self(true));
+                let params = std::iter::once("rustmasker").chain(job.params.iter().map(String::as_str));
+                let matches = command.try_get_matches_from(params).map_err(manifest_error)?;
+                let run_option = matches.ids().find(|id| {
+                    RUN_OPTIONS.contains(&id.as_str())
+                        && matches.value_source(id.as_str()) == Some(ValueSource::CommandLine)
+                });
+                if let Some(id) = run_option {
+                    let option = id.as_str().replace('_', "-");
+                    let message = format!("--{} cannot be set per file in the manifest ({})", option, job.input);
+                    return Err(message.into());
+                }
+                // Per-file options are parsed after the command line and override it
+                let argv = args.argv.iter().cloned().chain(job.params.iter().map(OsString::from));
+                let cli = parse_cli(argv.collect()).map_err(manifest_error)?;
+                cli.into_mask_args().expect("masking subcommand")
+            };
+            job_args.input = vec![job.input];
+            job_args.output = Some(job.output);
+            job_args.manifest = None;
+            job_args.output_dir = None;
+            Ok(job_args)
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Some(jobs))
+}
+
+/// Check options and build the masking pipeline
+fn configure(args: &Args) -> Result<(MaskPipeline, Option<TailConfig>), Box<dyn std::error::Error>> {
+    // Validate k-mer size (u32 encoding supports up to k=15)
+    if args.kmer > 15 {
+        return Err(format!(
+            "k-mer size k={} exceeds maximum supported value (k ≤ 15)\n\
+             The u32 encoding uses 2 bits per base, limiting k to 15 bases (30 bits).\n\
+             For low-complexity masking, k=3 to k=7 is typically used.",
+            args.kmer
+        )
+        .into());
+    }
+
+    if args.kmer < 1 {
+        return Err(format!("k-mer size k={} is too small (k must be at least 1)", args.kmer).into());
+    }
+
+    if args.window <= args.kmer {
+        return Err(format!("window size {} must be larger than k={}", args.window, args.kmer).into());
+    }
+
+    // Validate compression levels if specified
+    for level in [args.compression_level, args.clean_level, args.partial_level, args.full_level]
+        .into_iter()
+        .flatten()
+    {
+        if level > 9 {
+            return Err(format!("compression level {} is invalid (must be 0-9)", level).into());
+        }
+    }
+
+    // Validate chunk size
+    if args.chunk_size < 1 {
+        return Err("chunk size must be at least 1".into());
+    }
+
+    if args.progress == Some(0) {
+        return Err("--progress interval must be at least 1 second".into());
+    }
+
+    // Validate poly-X tail options
+    let poly_x_bases = args.poly_x_bases.to_ascii_uppercase().into_bytes();
+    if poly_x_bases.is_empty() || poly_x_bases.iter().any(|b| !b"ACGT".contains(b)) {
+        let message = format!("--poly-x-bases must only contain A, C, G or T (got '{}')", args.poly_x_bases);
+        return Err(message.into());
+    }
+
+    if args.poly_x_min_len < 1 {
+        return Err("--poly-x-min-len must be at least 1".into());
+    }
+
+    if args.entropy_bedgraph.is_some() && !(1..=args.window).contains(&args.entropy_step) {
+        return Err(format!("--entropy-step must be between 1 and the window size ({})", args.window).into());
+    }
+
+    if args.outfmt.is_some() && args.poly_x == PolyXMode::Trim {
+        return Err("--outfmt reports masked intervals and cannot be combined with --poly-x trim".into());
+    }
+
+    if !(0.0..1.0).contains(&args.poly_x_mismatch_rate) {
+        return Err("--poly-x-mismatch-rate must be in range [0, 1)".into());
+    }
+
+    let tail_config = if args.poly_x == PolyXMode::Off {
+        None
+    } else {
+        Some(TailConfig {
+            bases: poly_x_bases,
+            min_len: args.poly_x_min_len,
+            mismatch_rate: args.poly_x_mismatch_rate,
+            five_prime: !matches!(args.poly_x_ends, TailEnds::ThreePrime),
+            three_prime: !matches!(args.poly_x_ends, TailEnds::FivePrime),
+        })
+    };
+
+    if args.sdust_window <= 3 {
+        return Err("--sdust-window must be larger than 3 (sdust uses triplets)".into());
+    }
+
+    if args.sdust_threshold < 1 {
+        return Err("--sdust-threshold must be at least 1".into());
+    }
+
+    // Build the masking pipeline from the enabled detectors
+    let mut detectors = Vec::new();
+    if !args.no_entropy {
+        detectors.push(Detector::Entropy {
+            window: args.window,
+            threshold: args.threshold,
+            k: args.kmer,
+            method: EntropyMethod::from(&args.method),
+            short_reads: ShortReadPolicy::from(&args.short_reads),
+        });
+    }
+    if args.sdust {
+        detectors.push(Detector::Sdust {
+            window: args.sdust_window,
+            threshold: args.sdust_threshold,
+        });
+    }
+    if args.homopolymer == HomopolymerMode::Mask {
+        detectors.push(Detector::Homopolymer { min_run: args.homopolymer_min_run });
+    }
+    if let (PolyXMode::Mask, Some(config)) = (&args.poly_x, &tail_config) {
+        detectors.push(Detector::PolyX { config: config.clone() });
+    }
+
+    // Poly-X trimming and homopolymer reports still work without any masking detector
+    if detectors.is_empty() && args.poly_x != PolyXMode::Trim && args.homopolymer != HomopolymerMode::Report {
+        let message = "no masking detector is enabled (use --entropy, --sdust, --homopolymer mask or --poly-x mask)";
+        return Err(message.into());
+    }
+
+    if let CombineRule::MinOverlap(n) = args.combine {
+        if n > detectors.len() {
+            return Err(format!(
+                "--combine min-overlap:{} requires at least {} detectors, but only {} enabled",
+                n, n, detectors.len()
+            )
+            .into());
+        }
+    }
+    let pipeline = MaskPipeline::new(detectors, args.combine).with_post_process(PostProcess {
+        min_len: args.min_mask_len,
+        max_gap: args.merge_gap,
+        padding: args.mask_padding,
+    });
+
+    if args.homopolymer == HomopolymerMode::Report && !args.writes_stats() {
+        warning!("--homopolymer report has no effect without --stats");
+    }
+
+    if args.chunk_size > 100000 {
+        warning!(
+            "chunk size {} is very large and may use excessive memory (1000-10000 is recommended for most systems)",
+            args.chunk_size
+        );
+    }
+
+    Ok((pipeline, tail_config))
+}
+
+/// Mask the input (or with --concat, all inputs in turn) into the output, returning the
+/// statistics of each input
+fn mask_file(
+    args: &Args,
+    pipeline: &MaskPipeline,
+    tail_config: Option<&TailConfig>,
+) -> Result<Vec<FileStats>, Box<dyn std::error::Error>> {
+    // Create reader from file or stdin
+    let inputs: Vec<Option<&str>> = if args.input.is_empty() {
+        vec![None]
+    } else {
+        args.input.iter().map(|input| Some(input.as_str())).collect()
+    };
+    let mut progress = input_progress(inputs[0], args);
+    let mut reader = RecordReader::open_counted(inputs[0], Some(progress.counter()))?;
+
+    // Output format follows the output extension, otherwise the input format
+    // (dustmasker formats are always text)
+    let output_format = match args.outfmt {
+        Some(_) => SequenceFormat::Fastx,
+        None => args
+            .output
+            .as_deref()
+            .and_then(SequenceFormat::from_path)
+            .unwrap_or(reader.format()),
+    };
+    if output_format != SequenceFormat::Fastx && reader.header().is_none() {
+        return Err("SAM/BAM output requires SAM or BAM input".into());
+    }
+    if args.masked_count_tag.is_some() && output_format == SequenceFormat::Fastx {
+        warning!("--masked-count-tag has no effect with FASTQ output");
+    }
+    let output_compression = compression_for(
+        args.output.as_deref(),
+        output_format,
+        args.output_format.as_ref(),
+        args.compression_level,
+    );
+    if args.output_format.is_some() && output_format == SequenceFormat::Bam {
+        warning!("--output-format has no effect with BAM output (always BGZF)");
+    }
+
+    if args.gzi && (output_compression != Compression::Bgzf || args.output.is_none()) {
+        return Err("--gzi requires BGZF output to a file (use --output-format bgzf or a .bgz extension)".into());
+    }
+
+    let main_writer = open_writer(
+        args.output.as_deref(),
+        output_format,
+        output_compression,
+        args.compression_level,
+        args.gzi,
+        reader.header(),
+        args,
+    )?;
+
+    // Separate outputs by masking outcome follow the main output format and -c unless their
+    // extension and level say otherwise
+    let split_writer = |path: &Option<String>, level: Option<u32>| -> io::Result<Option<RecordWriter>> {
+        let Some(path) = path else { return Ok(None) };
+        let format = SequenceFormat::from_path(path).unwrap_or(output_format);
+        if format != SequenceFormat::Fastx && reader.header().is_none() {
+            let message = format!("SAM/BAM output ({}) requires SAM or BAM input", path);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let level = level.or(args.compression_level);
+        let compression = compression_for(Some(path), format, None, level);
+        open_writer(Some(path), format, compression, level, false, reader.header(), args).map(Some)
+    };
+    let mut writer = OutputStreams {
+        clean: split_writer(&args.clean_output, args.clean_level)?,
+        partial: split_writer(&args.partial_output, args.partial_level)?,
+        full: split_writer(&args.full_output, args.full_level)?,
+        main: main_writer,
+        entropy: None,
+    };
+    if let Some(path) = &args.entropy_bedgraph {
+        let compression = Compression::from_path(path).unwrap_or(Compression::None);
+        let level = args.compression_level.unwrap_or(1);
+        let mut track = compress_writer(File::create(path)?, compression, level, args.threads)?;
+        write_bedgraph_header(&mut track, args.window, args.kmer)?;
+        writer.entropy = Some((track, args.window, args.entropy_step));
+    }
+
+    let mut report = match &args.report {
+        Some(report_path) => {
+            let mut writer = BufWriter::new(File::create(report_path)?);
+            // Concatenated inputs add the input file of each read
+            if args.concat {
+                writeln!(writer, "read_id\tlength\tmasked_bases\tintervals\tfile")?;
+            } else {
+                writeln!(writer, "read_id\tlength\tmasked_bases\tintervals")?;
+            }
+            Some(ReadReport { writer, file: None })
+        }
+        None => None,
+    };
+
+    let mut files = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        if i > 0 {
+            progress = input_progress(*input, args);
+            reader = RecordReader::open_counted(*input, Some(progress.counter()))?;
+            let header = match &writer.main {
+                RecordWriter::Sam(_, header) | RecordWriter::Bam(_, header) => Some(header),
+                RecordWriter::Fastq(..) | RecordWriter::Dust(..) => None,
+            };
+            if header.is_some_and(|header| reader.header() != Some(header)) {
+                return Err(format!(
+                    "SAM/BAM header of {} differs from the first input; concatenated SAM/BAM output needs identical headers",
+                    input.unwrap_or("-")
+                )
+                .into());
+            }
+        }
+        let input_name = input.unwrap_or("-").to_string();
+        if let Some(report) = report.as_mut() {
+            report.file = args.concat.then(|| input_name.clone());
+        }
+        let label = (args.label_reads || !args.labels.is_empty()).then(|| {
+            args.labels.get(i).cloned().unwrap_or_else(|| file_label(&input_name))
+        });
+        let mut source = InputFile { label, progress };
+        let stats = mask_reads(&mut reader, &mut writer, &mut report, &mut source, args, pipeline, tail_config)?;
+        progress = source.progress;
+        files.push(FileStats { input: input_name, output: args.output.clone(), stats });
+    }
+
+    writer.finish()?;
+    if let Some(report) = report.as_mut() {
+        report.writer.flush()?;
+    }
+
+    Ok(files)
+}
+
+/// Progress of an input, reported every --progress seconds (otherwise only summarized with -v)
+fn input_progress(input: Option<&str>, args: &Args) -> Progress {
+    Progress::for_input(input, args.progress.map(Duration::from_secs))
+}
+
+/// Summarize an input with --progress or -v
+fn report_done(progress: &Progress, args: &Args, reads: u64, bases: u64, masked_bases: u64) {
+    let summary = progress.summary(reads, bases, masked_bases);
+    if args.progress.is_some() {
+        eprintln!("Done: {}", summary);
+    } else {
+        info!("Done: {}", summary);
+    }
+}
+
+/// Label added to the read IDs of an input, and its progress
+struct InputFile {
+    label: Option<String>,
+    progress: Progress,
+}
+
+/// Per-read report, and the input file of the current reads if there are several
+struct ReadReport {
+    writer: BufWriter<File>,
+    file: Option<String>,
+}
+
+/// Mask all reads of one input, returning its statistics
+fn mask_reads(
+    reader: &mut RecordReader,
+    writer: &mut OutputStreams,
+    report: &mut Option<ReadReport>,
+    source: &mut InputFile,
+    args: &Args,
+    pipeline: &MaskPipeline,
+    tail_config: Option<&TailConfig>,
+) -> Result<MaskingStats, Box<dyn std::error::Error>> {
+    let mut stats = MaskingStats::new();
+    stats.config = args.effective_config.clone();
+    match args.homopolymer {
+        HomopolymerMode::Off => {}
+        HomopolymerMode::Mask => {
+            stats.homopolymer = Some(HomopolymerStats::new("mask", args.homopolymer_min_run));
+        }
+        HomopolymerMode::Report => {
+            stats.homopolymer = Some(HomopolymerStats::new("report", args.homopolymer_min_run));
+        }
+    }
+    if !args.no_entropy {
+        let policy = args.short_reads.to_possible_value().unwrap();
+        stats.short_reads = Some(ShortReadStats::new(policy.get_name(), args.window));
+    }
+    if let Some(config) = tail_config {
+        let mode = if args.poly_x == PolyXMode::Trim { "trim" } else { "mask" };
+        stats.poly_x = Some(PolyXStats::new(mode, config.clone()));
+    }
+
+    // Process reads in chunks
+    let mut chunk: Vec<SeqRecord> = Vec::with_capacity(args.chunk_size);
+
+    while let Some(mut record) = reader.next_record()? {
+        source.progress.record_read(record.seq.len());
+        if let Some(label) = &source.label {
+            record.add_label(label);
+        }
+
+        // Trimming would invalidate the CIGAR of aligned records
+        if args.poly_x == PolyXMode::Trim
+            && record.alignment.as_ref().is_some_and(|r| !r.cigar().as_ref().is_empty())
+        {
+            return Err(format!(
+                "--poly-x trim is not supported for aligned records ({})",
+                String::from_utf8_lossy(&record.id)
+            )
+            .into());
+        }
+
+        // Store the record
+        chunk.push(record);
+
+        // Process chunk when full
+        if chunk.len() >= args.chunk_size {
+            process_and_write_chunk(&mut chunk, writer, report, args, pipeline, tail_config, &mut stats)?;
+            chunk.clear();
+            source.progress.record_masked(stats.bases, stats.masked_bases);
+        }
+    }
+
+    // Process remaining records
+    if !chunk.is_empty() {
+        process_and_write_chunk(&mut chunk, writer, report, args, pipeline, tail_config, &mut stats)?;
+    }
+
+    report_done(&source.progress, args, stats.reads, stats.bases, stats.masked_bases);
+
+    Ok(stats)
+}
+
+/// Masking result for a single read
+struct MaskedRead {
+    seq: Vec<u8>,
+    qual: Vec<u8>,
+    /// Bases changed to N by the masking pipeline
+    masked_bases: usize,
+    /// Combined masked intervals with the detectors that produced them
+    intervals: Vec<LabeledInterval>,
+    /// Homopolymer runs meeting the minimum length (empty if detection is off)
+    homopolymer_runs: Vec<HomopolymerRun>,
+    /// Poly-X tails found at the read ends (empty if detection is off)
+    tails: Vec<PolyXTail>,
+    /// SAM/BAM record with the masked SEQ/QUAL and updated tags (SAM/BAM input only)
+    alignment: Option<RecordBuf>,
+    /// Reason the MM/ML tags could not be updated and were removed instead
+    mod_tag_error: Option<String>,
+    /// SAM-style tags appended to the FASTQ header (empty unless --annotate-header is set)
+    header_tags: Vec<String>,
+    /// Entropy of each window of the input read (empty unless --entropy-bedgraph is set)
+    entropy_track: Vec<f64>,
+}
+
+/// Mask a single read with the masking pipeline, then trim poly-X tails if requested
+fn mask_read(
+    record: &SeqRecord,
+    args: &Args,
+    pipeline: &MaskPipeline,
+    tail_config: Option<&TailConfig>,
+) -> MaskedRead {
+    let intervals = pipeline.run(&record.seq);
+
+    let mut seq = record.seq.clone();
+    let mut qual = record.qual.clone();
+    let mut masked_bases = 0;
+    for labeled in &intervals {
+        let iv = labeled.interval;
+        masked_bases += mask_range(&mut seq, &mut qual, iv.start, iv.end);
+    }
+
+    // Homopolymer runs and poly-X tails are also detected outside the pipeline
+    // for the stats output (and for trimming)
+    let homopolymer_runs = if args.homopolymer == HomopolymerMode::Off {
+        Vec::new()
+    } else {
+        find_homopolymer_runs(&record.seq, &args.homopolymer_min_run)
+    };
+
+    let mut tails = Vec::new();
+    let mut keep_start = 0;
+    if let Some(config) = tail_config {
+        let (five_prime, three_prime) = find_poly_x_tails(&record.seq, config);
+        tails.extend(five_prime);
+        tails.extend(three_prime);
+
+        if args.poly_x == PolyXMode::Trim {
+            keep_start = five_prime.map_or(0, |t| t.end);
+            let keep_end = three_prime.map_or(seq.len(), |t| t.start);
+            // Bases masked inside the tails are removed rather than masked
+            masked_bases -= seq[..keep_start]
+                .iter()
+                .chain(seq[keep_end..].iter())
+                .zip(record.seq[..keep_start].iter().chain(record.seq[keep_end..].iter()))
+                .filter(|(&masked, &original)| masked == b'N' && original != b'N')
+                .count();
+            seq.truncate(keep_end);
+            seq.drain(..keep_start);
+            if !qual.is_empty() {
+                qual.truncate(keep_end);
+                qual.drain(..keep_start);
+            }
+        }
+    }
+
+    let entropy_track = match args.entropy_bedgraph {
+        Some(_) => entropy_track(&record.seq, args.window, args.kmer, EntropyMethod::from(&args.method)),
+        None => Vec::new(),
+    };
+
+    // Masked intervals in the coordinates of the written (possibly trimmed) read
+    let header_tags = match args.annotate_header {
+        Some(annotation) => {
+            let output_intervals: Vec<Interval> = intervals
+                .iter()
+                .filter_map(|li| {
+                    let start = li.interval.start.max(keep_start) - keep_start;
+                    let end = li.interval.end.min(keep_start + seq.len()).saturating_sub(keep_start);
+                    (start < end).then(|| Interval::new(start, end))
+                })
+                .collect();
+            HeaderAnnotation::from(annotation).tags(masked_bases, seq.len(), &output_intervals)
+        }
+        None => Vec::new(),
+    };
+
+    // Write the masked read back into the SAM/BAM record (also when the masked bases were
+    // already N, as their qualities change)
+    let mut mod_tag_error = None;
+    let alignment = record.alignment.as_ref().map(|original| {
+        let mut alignment = original.clone();
+        if !intervals.is_empty() || seq.len() != record.seq.len() {
+            set_sequence_and_quality(&mut alignment, &seq, &qual);
+            match args.mod_tags {
+                ModTags::Update => {
+                    let masked: Vec<Interval> = intervals.iter().map(|li| li.interval).collect();
+                    if let Err(error) = update_modification_tags(&mut alignment, &record.seq, &masked, keep_start) {
+                        strip_modification_tags(&mut alignment);
+                        mod_tag_error = Some(error);
+                    }
+                }
+                ModTags::Strip => strip_modification_tags(&mut alignment),
+            }
+        }
+        if let Some(tag) = args.masked_count_tag {
+            set_int_tag(&mut alignment, tag, masked_bases);
+        }
+        set_text_tags(&mut alignment, &header_tags).expect("masking tags are valid SAM tags");
+        alignment
+    });
+
+    MaskedRead {
+        seq,
+        qual,
+        masked_bases,
+        intervals,
+        homopolymer_runs,
+        tails,
+        alignment,
+        mod_tag_error,
+        header_tags,
+        entropy_track,
+    }
+}
+
+/// Read ID for reports: the header up to the first whitespace
+fn read_id(header: &[u8]) -> &[u8] {
+    let end = header
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .unwrap_or(header.len());
+    &header[..end]
+}
+
+/// Write one line of the per-read report
+fn write_report_line(
+    report: &mut ReadReport,
+    record: &SeqRecord,
+    result: &MaskedRead,
+    pipeline: &MaskPipeline,
+) -> io::Result<()> {
+    let intervals: Vec<String> = result
+        .intervals
+        .iter()
+        .map(|li| {
+            format!(
+                "{}-{}:{}",
+                li.interval.start,
+                li.interval.end,
+                pipeline.source_names(li.sources)
+            )
+        })
+        .collect();
+    write!(
+        report.writer,
+        "{}\t{}\t{}\t{}",
+        String::from_utf8_lossy(read_id(&record.id)),
+        record.seq.len(),
+        result.masked_bases,
+        if intervals.is_empty() { ".".to_string() } else { intervals.join(",") }
+    )?;
+    match &report.file {
+        Some(file) => writeln!(report.writer, "\t{}", file),
+        None => writeln!(report.writer),
+    }
+}
+
+/// Process a chunk of reads in parallel and write results
+fn process_and_write_chunk(
+    chunk: &mut Vec<SeqRecord>,
+    writer: &mut OutputStreams,
+    report: &mut Option<ReadReport>,
+    args: &Args,
+    pipeline: &MaskPipeline,
+    tail_config: Option<&TailConfig>,
+    stats: &mut MaskingStats,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Process chunk in parallel using selected method
+    let results: Vec<MaskedRead> = chunk
+        .par_iter()
+        .map(|record| mask_read(record, args, pipeline, tail_config))
+        .collect();
+
+    // Run-length histograms are only needed for the stats output
+    if let Some(homopolymer) = stats.homopolymer.as_mut() {
+        if args.writes_stats() {
+            let histogram = chunk
+                .par_iter()
+                .fold(RunLengthHistogram::new, |mut hist, record| {
+                    hist.add_sequence(&record.seq);
+                    hist
+                })
+                .reduce(RunLengthHistogram::new, |mut a, b| {
+                    a.merge(&b);
+                    a
+                });
+            homopolymer.histogram.merge(&histogram);
+        }
+    }
+
+    // Write results in order (sequential to preserve order)
+    for (i, result) in results.iter().enumerate() {
+        stats.record_read(chunk[i].seq.len(), result.masked_bases);
+        if let Some(short_reads) = stats.short_reads.as_mut() {
+            let entropy = pipeline.source_mask("entropy");
+            let entropy_masked = result.intervals.iter().any(|li| li.sources & entropy != 0);
+            short_reads.record_read(chunk[i].seq.len(), entropy_masked);
+        }
+        if let Some(homopolymer) = stats.homopolymer.as_mut() {
+            homopolymer.record_runs(&result.homopolymer_runs);
+        }
+        if let Some(poly_x) = stats.poly_x.as_mut() {
+            poly_x.record_tails(&result.tails);
+        }
+        if let Some(report) = report.as_mut() {
+            write_report_line(report, &chunk[i], result, pipeline)?;
+        }
+
+        if let Some(error) = &result.mod_tag_error {
+            warning!(
+                "removed invalid MM/ML tags from read {}: {}",
+                String::from_utf8_lossy(&chunk[i].id),
+                error
+            );
+        }
+        writer.write_record(&chunk[i], result)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask_args(argv: &[&str]) -> Args {
+        let argv = std::iter::once("rustmasker").chain(argv.iter().copied()).map(OsString::from);
+        parse_cli(argv.collect()).unwrap().into_mask_args().unwrap()
+    }
+
+    fn masked_read(argv: &[&str], seq: &[u8]) -> (SeqRecord, MaskedRead) {
+        let args = mask_args(argv);
+        let (pipeline, tail_config) = configure(&args).unwrap();
+        let record = SeqRecord {
+            id: b"read_1".to_vec(),
+            seq: seq.to_vec(),
+            qual: vec![b'I'; seq.len()],
+            plus: Vec::new(),
+            alignment: None,
+        };
+        let result = mask_read(&record, &args, &pipeline, tail_config.as_ref());
+        (record, result)
+    }
+
+    fn outcome(argv: &[&str], seq: &[u8]) -> MaskOutcome {
+        let (record, result) = masked_read(argv, seq);
+        MaskOutcome::of(&record, &result)
+    }
+
+    #[test]
+    fn test_mask_outcome() {
+        let homopolymer = ["--no-entropy", "--homopolymer", "mask", "--homopolymer-min-run", "8"];
+        assert_eq!(outcome(&homopolymer, b"ACGTTGCAAGCTTCGATCGG"), MaskOutcome::Clean);
+        assert_eq!(outcome(&homopolymer, b"ACGTTGCAAAAAAAAAATCGG"), MaskOutcome::Partial);
+        assert_eq!(outcome(&homopolymer, b"AAAAAAAAAAAA"), MaskOutcome::Full);
+        // Reads that are all N are clean unless a detector masks them
+        assert_eq!(outcome(&homopolymer, b"NNNNNNNNNNNN"), MaskOutcome::Clean);
+        assert_eq!(outcome(&["--short-reads", "always"], b"NNNNNNNNNNNN"), MaskOutcome::Full);
+        // Masking bases that were already N still counts as masking
+        let (record, mut result) = masked_read(&homopolymer, b"ACGTTGCAAGCTNNNNNNNNNNNN");
+        assert_eq!(MaskOutcome::of(&record, &result), MaskOutcome::Clean);
+        result.intervals.push(LabeledInterval { interval: Interval::new(12, 24), sources: 1 });
+        assert_eq!(MaskOutcome::of(&record, &result), MaskOutcome::Partial);
+        // Trimming alone changes the read
+        let trim = ["--no-entropy", "--poly-x", "trim"];
+        assert_eq!(outcome(&trim, b"ACGTTGCAAGCTTCGATCGGAAAAAAAAAAAA"), MaskOutcome::Partial);
+        assert_eq!(outcome(&trim, b"AAAAAAAAAAAA"), MaskOutcome::Full);
+    }
+
+    #[test]
+    fn test_config_option() {
+        let option = |key: &str, value: ConfigValue| config_option(key, value).unwrap();
+        assert_eq!(option("sdust", ConfigValue::Flag(true)), vec!["--sdust"]);
+        assert_eq!(option("sdust", ConfigValue::Flag(false)), vec!["--no-sdust"]);
+        // An option with an optional value is set by `true`
+        assert_eq!(option("progress", ConfigValue::Flag(true)), vec!["--progress"]);
+        assert!(option("progress", ConfigValue::Flag(false)).is_empty());
+        assert_eq!(option("progress", ConfigValue::Values(vec!["10".into()])), vec!["--progress=10"]);
+        assert!(config_option("sdust", ConfigValue::Values(vec!["1".into()])).is_err());
+        assert!(config_option("no-such-option", ConfigValue::Flag(true)).is_err());
+    }
+
+    #[test]
+    fn test_configure_errors() {
+        assert!(configure(&mask_args(&[])).is_ok());
+        let error = configure(&mask_args(&["-k", "16"])).err().unwrap().to_string();
+        assert!(error.starts_with("k-mer size k=16 exceeds"));
+        let error = configure(&mask_args(&["--no-entropy"])).err().unwrap().to_string();
+        assert!(error.starts_with("no masking detector"));
+        assert!(configure(&mask_args(&["--no-entropy", "--poly-x", "trim"])).is_ok());
+    }
+
+    #[test]
+    fn test_batch_jobs_errors() {
+        assert!(batch_jobs(&mask_args(&["-i", "a.fq"])).unwrap().is_none());
+        assert!(batch_jobs(&mask_args(&["-i", "a.fq", "b.fq"])).is_err());
+        assert!(batch_jobs(&mask_args(&["-i", "a.fq", "--label-reads"])).is_err());
+        let jobs = batch_jobs(&mask_args(&["-i", "a.fq", "b.fq", "--output-dir", "out"])).unwrap().unwrap();
+        assert_eq!(jobs.len(), 2);
+    }
+
+    #[test]
+    fn test_batch_jobs_manifest_options() {
+        let manifest = std::env::temp_dir().join(format!("rustmasker-{}-manifest.tsv", std::process::id()));
+        let manifest_jobs = |row: &str| {
+            std::fs::write(&manifest, format!("a.fq\tout/a.fq\nb.fq\tout/b.fq\t{}\n", row)).unwrap();
+            batch_jobs(&mask_args(&["--manifest", manifest.to_str().unwrap(), "-k", "3", "-w", "40"]))
+        };
+        // Per-file options override the command line, which the other options still come from
+        let jobs = manifest_jobs("--window 64 --sdust").unwrap().unwrap();
+        assert_eq!((jobs[0].window, jobs[0].kmer, jobs[0].sdust), (40, 3, false));
+        assert_eq!((jobs[1].window, jobs[1].kmer, jobs[1].sdust), (64, 3, true));
+        assert_eq!(jobs[1].input, vec!["b.fq"]);
+        // Run-level options are rejected in any form
+        for row in ["--threads 2", "-j2", "--input=c.fq", "-vv", "--quiet"] {
+            let error = manifest_jobs(row).err().unwrap().to_string();
+            assert!(error.contains("cannot be set per file"), "{}", error);
+        }
+        let error = manifest_jobs("--window").err().unwrap().to_string();
+        assert!(error.starts_with("in the manifest options for b.fq:"));
+        std::fs::remove_file(manifest).unwrap();
+    }
 }
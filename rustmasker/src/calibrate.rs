@@ -0,0 +1,210 @@
+// Threshold calibration
+//
+// Whether a base is masked depends only on the lowest entropy among the
+// windows covering it, so a single pass over a sample of reads gives the
+// masked fraction at every threshold of a sweep. Reads shorter than the
+// window use the whole-read entropy, as with `--short-reads whole-read`.
+
+use std::collections::VecDeque;
+
+use crate::{entropy_track, get_kmers, shannon_entropy, EntropyMethod};
+
+/// Number of equal-width bins of the window entropy histogram over [0, 1]
+pub const HISTOGRAM_BINS: usize = 100;
+
+/// Entropy of a sequence shorter than the window, as used by `--short-reads whole-read`
+fn whole_sequence_entropy(sequence: &[u8], k: usize) -> f64 {
+    let seq_len = sequence.len();
+    let total_kmers = if seq_len >= k { seq_len - k + 1 } else { 0 };
+    shannon_entropy(&get_kmers(sequence, k), total_kmers)
+}
+
+/// Sliding minimum of an entropy track over the windows [start, start + window) covering
+/// each base: a base is masked at threshold t exactly if its value is below t
+fn covering_minimum(track: &[f64], window: usize) -> Vec<f64> {
+    let seq_len = track.len() + window - 1;
+    let mut mins = Vec::with_capacity(seq_len);
+    let mut candidates: VecDeque<usize> = VecDeque::new();
+    for pos in 0..seq_len {
+        // Window starting at pos enters (if it exists), windows ending before pos leave
+        if pos < track.len() {
+            while candidates.back().is_some_and(|&i| track[i] >= track[pos]) {
+                candidates.pop_back();
+            }
+            candidates.push_back(pos);
+        }
+        while candidates.front().is_some_and(|&i| i + window <= pos) {
+            candidates.pop_front();
+        }
+        mins.push(track[*candidates.front().expect("every base is covered by a window")]);
+    }
+    mins
+}
+
+/// Masking outcome of a sample of reads at a sweep of thresholds, for one window and k
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    pub window: usize,
+    pub k: usize,
+    /// Thresholds in ascending order
+    pub thresholds: Vec<f64>,
+    pub reads: u64,
+    pub bases: u64,
+    /// Bases that would be masked at each threshold
+    pub masked_bases: Vec<u64>,
+    /// Reads with at least one base masked at each threshold
+    pub masked_reads: Vec<u64>,
+    /// Entropies of all windows (whole reads if shorter than the window), in
+    /// [`HISTOGRAM_BINS`] bins
+    pub histogram: Vec<u64>,
+}
+
+impl Calibration {
+    pub fn new(window: usize, k: usize, thresholds: &[f64]) -> Self {
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_by(f64::total_cmp);
+        Self {
+            window,
+            k,
+            masked_bases: vec![0; thresholds.len()],
+            masked_reads: vec![0; thresholds.len()],
+            thresholds,
+            reads: 0,
+            bases: 0,
+            histogram: vec![0; HISTOGRAM_BINS],
+        }
+    }
+
+    /// Index of the first threshold above `entropy` (all later thresholds mask it)
+    fn first_masking(&self, entropy: f64) -> usize {
+        self.thresholds.partition_point(|&t| t <= entropy)
+    }
+
+    pub fn add_sequence(&mut self, sequence: &[u8]) {
+        self.reads += 1;
+        self.bases += sequence.len() as u64;
+        if sequence.is_empty() {
+            return;
+        }
+
+        let (entropies, mins) = if sequence.len() < self.window {
+            let entropy = whole_sequence_entropy(sequence, self.k);
+            (vec![entropy], vec![entropy; sequence.len()])
+        } else {
+            let track = entropy_track(sequence, self.window, self.k, EntropyMethod::Array);
+            let mins = covering_minimum(&track, self.window);
+            (track, mins)
+        };
+        for &entropy in &entropies {
+            let bin = ((entropy * HISTOGRAM_BINS as f64) as usize).min(HISTOGRAM_BINS - 1);
+            self.histogram[bin] += 1;
+        }
+
+        let mut newly_masked = vec![0u64; self.thresholds.len() + 1];
+        let mut read_min = f64::INFINITY;
+        for &entropy in &mins {
+            newly_masked[self.first_masking(entropy)] += 1;
+            read_min = read_min.min(entropy);
+        }
+        let mut masked = 0;
+        for (i, count) in self.masked_bases.iter_mut().enumerate() {
+            masked += newly_masked[i];
+            *count += masked;
+        }
+        let first = self.first_masking(read_min);
+        for count in &mut self.masked_reads[first..] {
+            *count += 1;
+        }
+    }
+
+    /// Add the counts of another calibration with the same parameters
+    pub fn merge(&mut self, other: &Calibration) {
+        self.reads += other.reads;
+        self.bases += other.bases;
+        for (a, b) in self.masked_bases.iter_mut().zip(&other.masked_bases) {
+            *a += b;
+        }
+        for (a, b) in self.masked_reads.iter_mut().zip(&other.masked_reads) {
+            *a += b;
+        }
+        for (a, b) in self.histogram.iter_mut().zip(&other.histogram) {
+            *a += b;
+        }
+    }
+
+    /// Fraction of bases masked at the i-th threshold (0 if there are no bases)
+    pub fn masked_fraction(&self, i: usize) -> f64 {
+        if self.bases == 0 {
+            0.0
+        } else {
+            self.masked_bases[i] as f64 / self.bases as f64
+        }
+    }
+}
+
+/// Thresholds from `start` to `end` (inclusive) in steps of `step`
+pub fn threshold_sweep(start: f64, end: f64, step: f64) -> Vec<f64> {
+    let n = ((end - start) / step + 1e-9).floor() as usize;
+    // Rounded so that e.g. 0.15 is not written as 0.15000000000000002
+    (0..=n).map(|i| ((start + i as f64 * step) * 1e9).round() / 1e9).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entropy_mask_intervals, EntropyMethod, ShortReadPolicy};
+
+    #[test]
+    fn test_calibration_matches_masking() {
+        let sequences: [&[u8]; 3] = [
+            b"ACGTTGCATGACCATGACTTGACAAAAAAAAAAAAAAAAAAAAACACACACACACACACAGGTACCATGACTTGACNACGT",
+            b"ACACACACAC",
+            b"GATTACAGATTACATTGCA",
+        ];
+        let (window, k) = (20, 3);
+        let thresholds = threshold_sweep(0.3, 0.9, 0.1);
+        assert_eq!(thresholds.len(), 7);
+        assert_eq!(thresholds[5].to_string(), "0.8");
+
+        let mut calibration = Calibration::new(window, k, &thresholds);
+        for sequence in sequences {
+            calibration.add_sequence(sequence);
+        }
+        assert_eq!(calibration.bases, sequences.iter().map(|s| s.len() as u64).sum::<u64>());
+        let windows: u64 = sequences
+            .iter()
+            .map(|s| if s.len() < window { 1 } else { (s.len() - window + 1) as u64 })
+            .sum();
+        assert_eq!(calibration.histogram.iter().sum::<u64>(), windows);
+
+        for (i, &threshold) in thresholds.iter().enumerate() {
+            let (mut masked_bases, mut masked_reads) = (0, 0);
+            for sequence in sequences {
+                let intervals = entropy_mask_intervals(
+                    sequence,
+                    window,
+                    threshold,
+                    k,
+                    EntropyMethod::Auto,
+                    ShortReadPolicy::WholeRead,
+                );
+                masked_bases += intervals.iter().map(|iv| iv.len() as u64).sum::<u64>();
+                masked_reads += !intervals.is_empty() as u64;
+            }
+            assert_eq!(calibration.masked_bases[i], masked_bases, "threshold {}", threshold);
+            assert_eq!(calibration.masked_reads[i], masked_reads, "threshold {}", threshold);
+        }
+
+        let mut merged = Calibration::new(window, k, &thresholds);
+        merged.merge(&calibration);
+        merged.merge(&calibration);
+        assert_eq!(merged.masked_bases[3], 2 * calibration.masked_bases[3]);
+        assert_eq!(merged.masked_fraction(3), calibration.masked_fraction(3));
+
+        // A read shorter than the window adds its whole-read entropy to the histogram once
+        let mut short = Calibration::new(window, k, &thresholds);
+        short.add_sequence(sequences[1]);
+        assert_eq!((short.reads, short.bases), (1, 10));
+        assert_eq!(short.histogram.iter().sum::<u64>(), 1);
+    }
+}
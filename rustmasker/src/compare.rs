@@ -0,0 +1,206 @@
+// Comparison of two masked outputs
+//
+// Reads of the two files are paired in order and must have the same IDs and
+// lengths. A base counts as masked if it is 'N', so outputs of rustmasker,
+// BBMask or any other N-masking tool can be compared. Differing reads are
+// classified by the likely cause, replacing the shell scripts once used to
+// debug differences from BBMask (dev/benchmarks/debug_differences.sh and
+// friends).
+
+use crate::intervals::Interval;
+
+/// Likely cause of a masking disagreement, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffClass {
+    /// The read is shorter than the window, so the short-read policy decides
+    ShortRead,
+    /// The disagreement is within a window of an N that was in the input
+    NHandling,
+    /// Both reads have the same masked runs, but some start or end at different positions
+    EdgeOffset,
+    /// Masked runs present in only one of the reads
+    Other,
+}
+
+impl DiffClass {
+    pub const ALL: [DiffClass; 4] =
+        [DiffClass::ShortRead, DiffClass::NHandling, DiffClass::EdgeOffset, DiffClass::Other];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiffClass::ShortRead => "short_read",
+            DiffClass::NHandling => "n_handling",
+            DiffClass::EdgeOffset => "edge_offset",
+            DiffClass::Other => "other",
+        }
+    }
+}
+
+/// Masking disagreement within one pair of reads
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadDiff {
+    /// Bases masked in the first read
+    pub masked_first: usize,
+    /// Bases masked in the second read
+    pub masked_second: usize,
+    /// Bases masked only in the first read
+    pub only_first: usize,
+    /// Bases masked only in the second read
+    pub only_second: usize,
+    /// Cause of the disagreement (None if the masking is the same)
+    pub class: Option<DiffClass>,
+}
+
+impl ReadDiff {
+    pub fn is_match(&self) -> bool {
+        self.only_first == 0 && self.only_second == 0
+    }
+}
+
+/// Runs of N in a sequence
+pub fn n_runs(seq: &[u8]) -> Vec<Interval> {
+    let mut runs: Vec<Interval> = Vec::new();
+    for (pos, &base) in seq.iter().enumerate() {
+        if base != b'N' {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if run.end == pos => run.end += 1,
+            _ => runs.push(Interval::new(pos, pos + 1)),
+        }
+    }
+    runs
+}
+
+/// Compare the masked (N) positions of two versions of a read
+///
+/// `original` is the unmasked read, if available, to find Ns that were already in the input;
+/// without it, runs of N shorter than the window in both reads are taken as input Ns.
+pub fn diff_read(first: &[u8], second: &[u8], original: Option<&[u8]>, window: usize) -> ReadDiff {
+    let mut diff = ReadDiff::default();
+    let mut differing = Vec::new();
+    for (pos, (&a, &b)) in first.iter().zip(second).enumerate() {
+        diff.masked_first += (a == b'N') as usize;
+        diff.masked_second += (b == b'N') as usize;
+        match (a == b'N', b == b'N') {
+            (true, false) => diff.only_first += 1,
+            (false, true) => diff.only_second += 1,
+            _ => continue,
+        }
+        differing.push(pos);
+    }
+    if diff.is_match() {
+        return diff;
+    }
+
+    let first_runs = n_runs(first);
+    let second_runs = n_runs(second);
+    let input_ns = match original {
+        Some(original) => n_runs(original),
+        None => first_runs
+            .iter()
+            .filter(|run| run.len() < window && second_runs.contains(run))
+            .copied()
+            .collect(),
+    };
+    let near_input_n = |pos: usize| {
+        input_ns.iter().any(|run| pos + window > run.start && pos < run.end + window)
+    };
+
+    diff.class = Some(if first.len() < window {
+        DiffClass::ShortRead
+    } else if differing.iter().any(|&pos| near_input_n(pos)) {
+        DiffClass::NHandling
+    } else if first_runs.len() == second_runs.len()
+        && first_runs.iter().zip(&second_runs).all(|(a, b)| a.start < b.end && b.start < a.end)
+    {
+        DiffClass::EdgeOffset
+    } else {
+        DiffClass::Other
+    });
+    diff
+}
+
+/// Totals over all compared reads
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    pub reads: u64,
+    pub bases: u64,
+    /// Reads whose masking differs
+    pub differing_reads: u64,
+    pub only_first_bases: u64,
+    pub only_second_bases: u64,
+    /// Differing reads with more bases masked in the first read
+    pub first_masks_more: u64,
+    /// Differing reads with more bases masked in the second read
+    pub second_masks_more: u64,
+    /// Differing reads of each class, in the order of [`DiffClass::ALL`]
+    pub classes: [u64; 4],
+}
+
+impl Comparison {
+    pub fn add(&mut self, len: usize, diff: &ReadDiff) {
+        self.reads += 1;
+        self.bases += len as u64;
+        if let Some(class) = diff.class {
+            self.differing_reads += 1;
+            self.only_first_bases += diff.only_first as u64;
+            self.only_second_bases += diff.only_second as u64;
+            self.first_masks_more += (diff.masked_first > diff.masked_second) as u64;
+            self.second_masks_more += (diff.masked_second > diff.masked_first) as u64;
+            self.classes[DiffClass::ALL.iter().position(|&c| c == class).unwrap()] += 1;
+        }
+    }
+
+    pub fn is_match(&self) -> bool {
+        self.differing_reads == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_read() {
+        let diff = diff_read(b"NNNACGTNN", b"ANNACGNNN", None, 4);
+        assert_eq!((diff.only_first, diff.only_second), (1, 1));
+        assert_eq!((diff.masked_first, diff.masked_second), (5, 5));
+        assert_eq!(diff.class, Some(DiffClass::EdgeOffset));
+        assert!(diff_read(b"ACNN", b"ACNN", None, 4).is_match());
+
+        let mut comparison = Comparison::default();
+        comparison.add(9, &diff);
+        comparison.add(4, &ReadDiff::default());
+        assert_eq!(comparison.differing_reads, 1);
+        assert_eq!(comparison.bases, 13);
+        assert_eq!(comparison.classes, [0, 0, 1, 0]);
+        assert!(!comparison.is_match());
+    }
+
+    #[test]
+    fn test_diff_classes() {
+        assert_eq!(n_runs(b"NNACNGN"), vec![Interval::new(0, 2), Interval::new(4, 5), Interval::new(6, 7)]);
+
+        // Shorter than the window
+        assert_eq!(diff_read(b"NNNN", b"ACGT", None, 10).class, Some(DiffClass::ShortRead));
+
+        // A masked run only in the second read
+        let first = b"ACGTACGTACGTACGTACGT";
+        let second = b"ACGTACGTNNNNNNNNACGT";
+        assert_eq!(diff_read(first, second, None, 4).class, Some(DiffClass::Other));
+
+        // Masking next to an input N, inferred from a short run in both reads
+        let first = b"ACGTACGTNCGTACGTACGT";
+        let second = b"ACGTACGTNCNNNNNNACGT";
+        assert_eq!(diff_read(first, second, None, 4).class, Some(DiffClass::NHandling));
+
+        // A run extended over an input N, which only the original read tells apart
+        let first = b"ACGTACGTACGTACGTNCGT";
+        let second = b"ACGTACGTACGTNNNNNCGT";
+        let original = b"ACGTACGTACGTACGTNCGT";
+        assert_eq!(diff_read(first, second, Some(original), 4).class, Some(DiffClass::NHandling));
+        let original = b"ACGTACGTACGTACGTACGT";
+        assert_eq!(diff_read(first, second, Some(original), 4).class, Some(DiffClass::EdgeOffset));
+    }
+}
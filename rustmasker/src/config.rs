@@ -0,0 +1,75 @@
+// Configuration files
+//
+// A TOML file sets masking options by their long names (`window = 64`,
+// `sdust = true`, `input = ["a.fq", "b.fq"]`); underscores and dashes are
+// interchangeable. The binary turns the values into command-line options
+// placed before those given by the user, so flags on the command line win.
+
+use toml::{Table, Value};
+
+/// Value of one configuration key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    /// A boolean, for flags such as `sdust = true`
+    Flag(bool),
+    /// One or more values, as they would be written on the command line
+    Values(Vec<String>),
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(format!("config key '{}': expected a string, number or boolean", key)),
+    }
+}
+
+/// Parse a TOML configuration into option names (with dashes) and values, in sorted key order
+pub fn parse_config(text: &str) -> Result<Vec<(String, ConfigValue)>, String> {
+    let table: Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+    let mut options = Vec::new();
+    for (key, value) in &table {
+        let value = match value {
+            Value::Boolean(b) => ConfigValue::Flag(*b),
+            Value::Array(values) => {
+                ConfigValue::Values(values.iter().map(|v| scalar(key, v)).collect::<Result<_, _>>()?)
+            }
+            Value::Table(_) => return Err(format!("config key '{}': tables are not supported", key)),
+            _ => ConfigValue::Values(vec![scalar(key, value)?]),
+        };
+        options.push((key.replace('_', "-"), value));
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let text = r#"
+            # Masking options
+            window = 64
+            threshold = 0.55
+            short_reads = "never"
+            sdust = true
+            gzi = false
+            labels = ["a", "b"]
+        "#;
+        let options = parse_config(text).unwrap();
+        let get = |key: &str| options.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+        assert_eq!(get("window"), Some(ConfigValue::Values(vec!["64".to_string()])));
+        assert_eq!(get("threshold"), Some(ConfigValue::Values(vec!["0.55".to_string()])));
+        assert_eq!(get("short-reads"), Some(ConfigValue::Values(vec!["never".to_string()])));
+        assert_eq!(get("sdust"), Some(ConfigValue::Flag(true)));
+        assert_eq!(get("gzi"), Some(ConfigValue::Flag(false)));
+        assert_eq!(get("labels"), Some(ConfigValue::Values(vec!["a".to_string(), "b".to_string()])));
+
+        assert!(parse_config("window = ").is_err());
+        assert!(parse_config("[mask]\nwindow = 64\n").is_err());
+        assert!(parse_config("labels = [[1]]\n").is_err());
+    }
+}
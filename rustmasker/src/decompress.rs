@@ -0,0 +1,479 @@
+// Threaded input decompression
+//
+// Decompression runs ahead of the parser on its own thread and hands over
+// decompressed data through a bounded channel, so it overlaps with parsing
+// and masking. BGZF input (bgzip, BAM) is made of independent blocks whose
+// sizes are stored in their headers, so batches of blocks are also inflated
+// in parallel. Other multi-member gzip files (e.g. `cat a.gz b.gz`) do not
+// record member sizes: members are found by inflating from every gzip header
+// signature in a batch of input in parallel and chaining the members whose
+// trailers check out from the start of the batch. Input is inflated as a
+// stream until a member ends inside a batch and another gzip header follows
+// it, so the single member of most gzip files is only inflated once; members
+// larger than a batch are also inflated as a stream.
+//
+// Each chunk of decompressed data carries the number of compressed bytes behind
+// it, so progress reports can follow the data the parser has consumed rather
+// than the input the decompression threads have read ahead.
+
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use flate2::bufread::GzDecoder;
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+use rayon::prelude::*;
+
+/// Bytes of decompressed data per message from the reader thread
+const CHUNK_SIZE: usize = 1 << 20;
+/// Messages buffered between the reader thread and the consumer
+const CHANNEL_BOUND: usize = 4;
+/// BGZF block header length (gzip header with the BC extra subfield)
+const BGZF_HEADER_LEN: usize = 18;
+/// Compressed bytes per batch of gzip members for each thread
+const MEMBER_BATCH_BYTES: usize = 4 << 20;
+/// Largest batch, as a multiple of the initial size, when members do not fit in a batch
+const MAX_BATCH_GROWTH: usize = 16;
+/// Largest decompressed size of a member inflated from a batch; larger members are streamed
+const MAX_MEMBER_INFLATE: usize = 64 << 20;
+
+/// Whether a stream starts with a BGZF block (gzip with a "BC" extra subfield)
+pub fn is_bgzf(head: &[u8]) -> bool {
+    head.len() >= BGZF_HEADER_LEN
+        && head[..4] == [0x1f, 0x8b, 0x08, 0x04]
+        && head[12..16] == [b'B', b'C', 0x02, 0x00]
+}
+
+/// Position in the compressed input, for progress reporting
+#[derive(Debug, Clone, Default)]
+pub struct InputPosition {
+    /// Compressed bytes read from the input so far (e.g. counted by a `CountingReader`)
+    pub read: Arc<AtomicU64>,
+    /// Compressed bytes behind the decompressed data returned by the reader so far
+    pub consumed: Arc<AtomicU64>,
+}
+
+/// Decompressed data and the compressed bytes read up to its end
+type Message = io::Result<(Vec<u8>, u64)>;
+
+/// Sending end of a decompression thread
+struct ChunkSender {
+    sender: SyncSender<Message>,
+    read: Arc<AtomicU64>,
+}
+
+impl ChunkSender {
+    /// Send a chunk, given the compressed bytes read but not yet behind it; returns false if
+    /// the consumer has gone away
+    fn send(&self, data: Vec<u8>, unread: usize) -> bool {
+        let end = self.read.load(Ordering::Relaxed).saturating_sub(unread as u64);
+        self.sender.send(Ok((data, end))).is_ok()
+    }
+
+    fn send_error(&self, e: io::Error) {
+        let _ = self.sender.send(Err(e));
+    }
+}
+
+/// Channel between a decompression thread and its reader
+fn channel(position: InputPosition) -> (ChunkSender, ChannelReader) {
+    let (sender, receiver) = sync_channel(CHANNEL_BOUND);
+    let reader = ChannelReader {
+        receiver,
+        buf: Vec::new(),
+        pos: 0,
+        buf_start: 0,
+        buf_end: 0,
+        consumed: position.consumed,
+    };
+    (ChunkSender { sender, read: position.read }, reader)
+}
+
+/// Reader receiving decompressed data from a background thread
+pub struct ChannelReader {
+    receiver: Receiver<Message>,
+    buf: Vec<u8>,
+    pos: usize,
+    /// Compressed bytes behind the data before and after `buf`
+    buf_start: u64,
+    buf_end: u64,
+    consumed: Arc<AtomicU64>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            match self.receiver.recv() {
+                Ok(message) => {
+                    let (data, end) = message?;
+                    self.buf = data;
+                    self.pos = 0;
+                    self.buf_start = self.buf_end;
+                    self.buf_end = end.max(self.buf_start);
+                }
+                // The sender is dropped at the end of the input
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        // Compressed bytes behind the data read so far, interpolated within the chunk
+        let within = (self.buf_end - self.buf_start) as u128 * self.pos as u128 / self.buf.len() as u128;
+        self.consumed.store(self.buf_start + within as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Send a reader's data in chunks, given a function of the compressed bytes the reader has
+/// read ahead; returns false on error or if the consumer has gone away
+fn pump<R: Read>(mut reader: R, sender: &ChunkSender, unread: impl Fn(&R) -> usize) -> bool {
+    loop {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        match reader.by_ref().take(CHUNK_SIZE as u64).read_to_end(&mut chunk) {
+            Ok(0) => return true,
+            Ok(_) => {
+                if !sender.send(chunk, unread(&reader)) {
+                    return false;
+                }
+            }
+            Err(e) => {
+                sender.send_error(e);
+                return false;
+            }
+        }
+    }
+}
+
+/// Read a decoder (or any reader) on its own thread
+pub fn threaded_reader<R: Read + Send + 'static>(reader: R, position: InputPosition) -> ChannelReader {
+    let (sender, receiver) = channel(position);
+    thread::spawn(move || {
+        pump(reader, &sender, |_| 0);
+    });
+    receiver
+}
+
+/// Read one BGZF block, or return the bytes read so far if the next member is not BGZF
+enum Block {
+    Bgzf(Vec<u8>),
+    Other(Vec<u8>),
+    Eof,
+}
+
+fn read_block<R: Read>(reader: &mut R) -> io::Result<Block> {
+    let mut header = Vec::with_capacity(BGZF_HEADER_LEN);
+    reader.by_ref().take(BGZF_HEADER_LEN as u64).read_to_end(&mut header)?;
+    if header.is_empty() {
+        return Ok(Block::Eof);
+    }
+    if !is_bgzf(&header) {
+        return Ok(Block::Other(header));
+    }
+    let block_size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+    if block_size < BGZF_HEADER_LEN + 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid BGZF block size"));
+    }
+    let mut block = header;
+    block.resize(block_size, 0);
+    reader.read_exact(&mut block[BGZF_HEADER_LEN..])?;
+    Ok(Block::Bgzf(block))
+}
+
+/// Inflate one BGZF block and check its CRC and length
+fn inflate_block(block: &[u8]) -> io::Result<Vec<u8>> {
+    let n = block.len();
+    let crc = u32::from_le_bytes(block[n - 8..n - 4].try_into().unwrap());
+    let isize = u32::from_le_bytes(block[n - 4..].try_into().unwrap()) as usize;
+    let mut data = Vec::with_capacity(isize);
+    DeflateDecoder::new(&block[BGZF_HEADER_LEN..n - 8]).read_to_end(&mut data)?;
+
+    let mut actual_crc = Crc::new();
+    actual_crc.update(&data);
+    if data.len() != isize || actual_crc.sum() != crc {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt BGZF block"));
+    }
+    Ok(data)
+}
+
+/// Decompress BGZF input, inflating batches of blocks in parallel on the rayon thread pool
+///
+/// If a non-BGZF gzip member follows (e.g. files concatenated with plain gzip), the rest of
+/// the input is decompressed as by parallel_gzip_reader().
+pub fn parallel_bgzf_reader<R: Read + Send + 'static>(mut reader: R, position: InputPosition) -> ChannelReader {
+    let (sender, receiver) = channel(position);
+    let batch_blocks = rayon::current_num_threads() * 4;
+
+    thread::spawn(move || {
+        let mut batch = Vec::with_capacity(batch_blocks);
+        loop {
+            let (block, done) = match read_block(&mut reader) {
+                Ok(Block::Bgzf(block)) => (Some(block), false),
+                Ok(Block::Eof) => (None, true),
+                Ok(Block::Other(header)) => {
+                    if send_batch(&mut batch, &sender) {
+                        pump_gzip_members(Cursor::new(header).chain(reader), &sender, member_batch_bytes());
+                    }
+                    return;
+                }
+                Err(e) => {
+                    if send_batch(&mut batch, &sender) {
+                        sender.send_error(e);
+                    }
+                    return;
+                }
+            };
+            batch.extend(block);
+            if (done || batch.len() >= batch_blocks) && !send_batch(&mut batch, &sender) {
+                return;
+            }
+            if done {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// Inflate and send a batch of blocks in order; returns false on error or if the consumer
+/// has gone away
+fn send_batch(batch: &mut Vec<Vec<u8>>, sender: &ChunkSender) -> bool {
+    if batch.is_empty() {
+        return true;
+    }
+    let inflated: io::Result<Vec<Vec<u8>>> = batch.par_iter().map(|block| inflate_block(block)).collect();
+    batch.clear();
+    match inflated {
+        Ok(blocks) => sender.send(blocks.concat(), 0),
+        Err(e) => {
+            sender.send_error(e);
+            false
+        }
+    }
+}
+
+/// Decompress gzip input, inflating batches of members in parallel on the rayon thread pool
+///
+/// The first member, members larger than a batch and the only member of a single-member file
+/// are inflated sequentially on the reader thread.
+pub fn parallel_gzip_reader<R: Read + Send + 'static>(reader: R, position: InputPosition) -> ChannelReader {
+    gzip_member_reader(reader, position, member_batch_bytes())
+}
+
+fn gzip_member_reader<R: Read + Send + 'static>(
+    reader: R,
+    position: InputPosition,
+    batch_bytes: usize,
+) -> ChannelReader {
+    let (sender, receiver) = channel(position);
+    thread::spawn(move || pump_gzip_members(reader, &sender, batch_bytes));
+    receiver
+}
+
+/// Compressed bytes per batch of gzip members
+fn member_batch_bytes() -> usize {
+    rayon::current_num_threads() * MEMBER_BATCH_BYTES
+}
+
+/// Inflate one gzip member from the start of `data`, returning its data and compressed length
+///
+/// Fails if the member is incomplete, its CRC or length do not match, or it inflates to more
+/// than `max_len` bytes.
+fn inflate_member(data: &[u8], max_len: usize) -> io::Result<(Vec<u8>, usize)> {
+    let mut decoder = GzDecoder::new(data);
+    let mut inflated = Vec::new();
+    decoder.by_ref().take(max_len as u64 + 1).read_to_end(&mut inflated)?;
+    if inflated.len() > max_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "gzip member too large for a batch"));
+    }
+    let rest = decoder.into_inner();
+    Ok((inflated, data.len() - rest.len()))
+}
+
+/// Inflate the complete gzip members at the start of `data` in parallel, returning their
+/// data in order and the number of bytes they take up
+fn split_members(data: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    // Possible member starts: the gzip magic, deflate method and no reserved flags
+    let candidates: Vec<usize> = (0..data.len().saturating_sub(3))
+        .filter(|&i| data[i..i + 3] == [0x1f, 0x8b, 0x08] && data[i + 3] & 0xe0 == 0)
+        .collect();
+    let mut members: Vec<Option<(Vec<u8>, usize)>> =
+        candidates.par_iter().map(|&start| inflate_member(&data[start..], MAX_MEMBER_INFLATE).ok()).collect();
+
+    // Follow the members from the start; other candidates were inside compressed data
+    let mut inflated = Vec::new();
+    let mut pos = 0;
+    while let Ok(i) = candidates.binary_search(&pos) {
+        let Some((member, len)) = members[i].take() else { break };
+        inflated.push(member);
+        pos += len;
+    }
+    (inflated, pos)
+}
+
+/// Send the decompressed data of gzip input, inflating batches of members in parallel
+///
+/// Members are inflated as a stream until one ends inside a batch and is followed by another
+/// gzip header; only then are batches split into members in parallel.
+fn pump_gzip_members<R: Read>(reader: R, sender: &ChunkSender, mut batch_bytes: usize) {
+    let max_batch_bytes = batch_bytes * MAX_BATCH_GROWTH;
+    let mut reader = BufReader::new(reader);
+    let mut batch = Vec::with_capacity(batch_bytes);
+    let mut parallel = false;
+    loop {
+        let missing = batch_bytes.saturating_sub(batch.len());
+        if let Err(e) = reader.by_ref().take(missing as u64).read_to_end(&mut batch) {
+            sender.send_error(e);
+            return;
+        }
+        if batch.is_empty() {
+            return;
+        }
+
+        if parallel {
+            let (members, len) = split_members(&batch);
+            if !members.is_empty() {
+                batch.drain(..len);
+                if !sender.send(members.concat(), batch.len() + reader.buffer().len()) {
+                    return;
+                }
+                continue;
+            }
+            // The next member does not fit in the batch (or is corrupt): use larger batches in
+            // case the following members are as large
+            batch_bytes = (batch_bytes * 2).min(max_batch_bytes);
+        }
+
+        // Inflate the next member as a stream
+        let mut decoder = GzDecoder::new(Cursor::new(std::mem::take(&mut batch)).chain(reader.by_ref()));
+        let streamed = pump(&mut decoder, sender, |decoder| {
+            let (batch, reader) = decoder.get_ref().get_ref();
+            batch.get_ref().len() - batch.position() as usize + reader.buffer().len()
+        });
+        if !streamed {
+            return;
+        }
+        // Keep the compressed data read past the end of the member; if the member ended inside
+        // the batch and another member follows, split the following batches in parallel
+        let (rest, _) = decoder.into_inner().into_inner();
+        let pos = rest.position() as usize;
+        batch = rest.into_inner().split_off(pos);
+        parallel = batch.starts_with(&[0x1f, 0x8b, 0x08]);
+        batch.extend_from_slice(reader.buffer());
+        reader.consume(reader.buffer().len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bgzf::BgzfWriter;
+    use flate2::read::MultiGzDecoder;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn test_data() -> Vec<u8> {
+        (0..30_000)
+            .flat_map(|i| format!("@read{}\nACGTACGTTTGACA\n+\nIIIIIIIIIIIIII\n", i).into_bytes())
+            .collect()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_parallel_bgzf_reader() {
+        let data = test_data();
+        let mut compressed = Vec::new();
+        {
+            let mut writer = BgzfWriter::new(&mut compressed, 1);
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+        }
+        assert!(is_bgzf(&compressed));
+        // A plain gzip member after the BGZF blocks is decompressed sequentially
+        compressed.extend(gzip(b"@tail\nACGT\n+\nIIII\n"));
+
+        let mut decompressed = Vec::new();
+        parallel_bgzf_reader(Cursor::new(compressed), InputPosition::default()).read_to_end(&mut decompressed).unwrap();
+        let mut expected = data.clone();
+        expected.extend_from_slice(b"@tail\nACGT\n+\nIIII\n");
+        assert_eq!(decompressed, expected);
+    }
+
+    #[test]
+    fn test_corrupt_bgzf_block() {
+        let mut compressed = Vec::new();
+        {
+            let mut writer = BgzfWriter::new(&mut compressed, 1);
+            writer.write_all(&test_data()).unwrap();
+            writer.finish().unwrap();
+        }
+        assert!(!is_bgzf(&gzip(b"ACGT")));
+        // Flip a byte of the first block's CRC
+        let bsize = u16::from_le_bytes([compressed[16], compressed[17]]) as usize + 1;
+        compressed[bsize - 8] ^= 0xff;
+        let mut decompressed = Vec::new();
+        assert!(parallel_bgzf_reader(Cursor::new(compressed), InputPosition::default()).read_to_end(&mut decompressed).is_err());
+    }
+
+    #[test]
+    fn test_parallel_gzip_reader() {
+        let data = test_data();
+        // Members of different sizes, as from concatenated gzip files
+        let mut compressed = Vec::new();
+        for part in [&data[..100], &data[100..500_000], &data[500_000..]] {
+            compressed.extend(gzip(part));
+        }
+        let (members, len) = split_members(&compressed);
+        assert_eq!((members.len(), len), (3, compressed.len()));
+        // Members inflating to more than the limit are left to be streamed
+        assert!(inflate_member(&compressed, 99).is_err());
+        assert_eq!(inflate_member(&compressed, 100).unwrap().0, &data[..100]);
+
+        let mut decompressed = Vec::new();
+        parallel_gzip_reader(Cursor::new(compressed.clone()), InputPosition::default()).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+
+        // A truncated last member is left for the next batch, and is an error at the end
+        let truncated = &compressed[..compressed.len() - 10];
+        let (members, len) = split_members(truncated);
+        assert_eq!(members.len(), 2);
+        assert!(len < truncated.len());
+        let mut decompressed = Vec::new();
+        assert!(parallel_gzip_reader(Cursor::new(truncated.to_vec()), InputPosition::default()).read_to_end(&mut decompressed).is_err());
+    }
+
+    #[test]
+    fn test_gzip_member_larger_than_batch() {
+        // Members larger than a batch are inflated as a stream, followed by parallel batches
+        let data = test_data();
+        let mut compressed = gzip(&data);
+        let batch_bytes = 4096;
+        assert!(compressed.len() > batch_bytes);
+        compressed.extend(gzip(b"@tail\nACGT\n+\nIIII\n"));
+        compressed.extend(gzip(&data[..1000]));
+        let mut decompressed = Vec::new();
+        gzip_member_reader(Cursor::new(compressed), InputPosition::default(), batch_bytes).read_to_end(&mut decompressed).unwrap();
+        let mut expected = data.clone();
+        expected.extend_from_slice(b"@tail\nACGT\n+\nIIII\n");
+        expected.extend_from_slice(&data[..1000]);
+        assert_eq!(decompressed, expected);
+    }
+
+    #[test]
+    fn test_threaded_reader() {
+        let data = test_data();
+        let mut decompressed = Vec::new();
+        threaded_reader(MultiGzDecoder::new(Cursor::new(gzip(&data))), InputPosition::default())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
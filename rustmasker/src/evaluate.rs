@@ -0,0 +1,232 @@
+// Masking accuracy against a truth set
+//
+// Truth intervals come from a BED file (e.g. `rustmasker simulate --truth`, or
+// the output of a reference masker) keyed by read name, with an optional
+// category in the fourth column. Predicted intervals of each read are scored
+// at base level (overlap with the truth) and at interval level (truth
+// intervals detected, predicted intervals supported by the truth).
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::intervals::{merge_intervals, total_length, Interval};
+
+/// Category of truth intervals without a fourth BED column
+pub const DEFAULT_CATEGORY: &str = "low_complexity";
+
+/// Truth intervals of each read, with their categories
+pub type TruthSet = HashMap<String, Vec<(Interval, String)>>;
+
+/// Parse a BED file of truth intervals (name, start, end and optional category)
+///
+/// Blank lines and `#`, `track` and `browser` lines are ignored.
+pub fn parse_truth_bed(text: &str) -> Result<TruthSet, String> {
+    let mut truth = TruthSet::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let coordinate = |field: Option<&&str>| field.and_then(|v| v.trim().parse::<usize>().ok());
+        let (Some(start), Some(end)) = (coordinate(fields.get(1)), coordinate(fields.get(2))) else {
+            return Err(format!("BED line {}: expected name, start and end separated by tabs", i + 1));
+        };
+        if start > end {
+            return Err(format!("BED line {}: start {} is after end {}", i + 1, start, end));
+        }
+        let category = fields.get(3).map_or(DEFAULT_CATEGORY, |c| c.trim());
+        truth
+            .entry(fields[0].to_string())
+            .or_default()
+            .push((Interval::new(start, end), category.to_string()));
+    }
+    for intervals in truth.values_mut() {
+        intervals.sort_by_key(|(iv, _)| (iv.start, iv.end));
+    }
+    Ok(truth)
+}
+
+/// Bases of `interval` covered by `intervals` (sorted and non-overlapping)
+fn covered(intervals: &[Interval], interval: &Interval) -> usize {
+    intervals
+        .iter()
+        .map(|iv| iv.end.min(interval.end).saturating_sub(iv.start.max(interval.start)))
+        .sum()
+}
+
+/// Base and interval counts of one category (or of all truth intervals)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub truth_intervals: u64,
+    /// Truth intervals with at least the minimum overlap fraction predicted
+    pub detected_intervals: u64,
+    pub truth_bases: u64,
+    /// Truth bases that were predicted
+    pub detected_bases: u64,
+}
+
+impl Counts {
+    fn merge(&mut self, other: &Counts) {
+        self.truth_intervals += other.truth_intervals;
+        self.detected_intervals += other.detected_intervals;
+        self.truth_bases += other.truth_bases;
+        self.detected_bases += other.detected_bases;
+    }
+
+    pub fn interval_sensitivity(&self) -> f64 {
+        ratio(self.detected_intervals, self.truth_intervals)
+    }
+
+    pub fn base_sensitivity(&self) -> f64 {
+        ratio(self.detected_bases, self.truth_bases)
+    }
+}
+
+/// Ratio, or 0 if the denominator is 0
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+/// Accuracy of predicted intervals over a set of reads
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    /// Fraction of a truth interval that must be predicted to count as detected
+    pub min_overlap: f64,
+    pub reads: u64,
+    /// All truth intervals (overlapping intervals merged for the base counts)
+    pub total: Counts,
+    pub categories: BTreeMap<String, Counts>,
+    pub predicted_intervals: u64,
+    /// Predicted intervals overlapping a truth interval
+    pub supported_intervals: u64,
+    pub predicted_bases: u64,
+    /// Predicted bases within a truth interval
+    pub supported_bases: u64,
+}
+
+impl Evaluation {
+    pub fn new(min_overlap: f64) -> Self {
+        Self {
+            min_overlap,
+            reads: 0,
+            total: Counts::default(),
+            categories: BTreeMap::new(),
+            predicted_intervals: 0,
+            supported_intervals: 0,
+            predicted_bases: 0,
+            supported_bases: 0,
+        }
+    }
+
+    /// Score the predicted intervals of one read (sorted, non-overlapping) against its truth
+    pub fn add_read(&mut self, truth: &[(Interval, String)], predicted: &[Interval]) {
+        self.reads += 1;
+        let detected = |iv: &Interval| {
+            !iv.is_empty() && covered(predicted, iv) as f64 >= self.min_overlap * iv.len() as f64
+        };
+        for (iv, category) in truth {
+            let counts = self.categories.entry(category.clone()).or_default();
+            counts.truth_intervals += 1;
+            counts.detected_intervals += detected(iv) as u64;
+            counts.truth_bases += iv.len() as u64;
+            counts.detected_bases += covered(predicted, iv) as u64;
+        }
+
+        let truth_intervals: Vec<Interval> = truth.iter().map(|(iv, _)| *iv).collect();
+        self.total.truth_intervals += truth.len() as u64;
+        self.total.detected_intervals += truth_intervals.iter().filter(|iv| detected(iv)).count() as u64;
+        let merged = merge_intervals(truth_intervals);
+        self.total.truth_bases += total_length(&merged) as u64;
+        let supported_bases: usize = predicted.iter().map(|iv| covered(&merged, iv)).sum();
+        self.total.detected_bases += supported_bases as u64;
+
+        self.predicted_intervals += predicted.len() as u64;
+        self.supported_intervals += predicted.iter().filter(|iv| covered(&merged, iv) > 0).count() as u64;
+        self.predicted_bases += total_length(predicted) as u64;
+        self.supported_bases += supported_bases as u64;
+    }
+
+    /// Add the counts of another evaluation with the same minimum overlap
+    pub fn merge(&mut self, other: &Evaluation) {
+        self.reads += other.reads;
+        self.total.merge(&other.total);
+        for (category, counts) in &other.categories {
+            self.categories.entry(category.clone()).or_default().merge(counts);
+        }
+        self.predicted_intervals += other.predicted_intervals;
+        self.supported_intervals += other.supported_intervals;
+        self.predicted_bases += other.predicted_bases;
+        self.supported_bases += other.supported_bases;
+    }
+
+    pub fn interval_precision(&self) -> f64 {
+        ratio(self.supported_intervals, self.predicted_intervals)
+    }
+
+    pub fn base_precision(&self) -> f64 {
+        ratio(self.supported_bases, self.predicted_bases)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_truth_bed() {
+        let text = "track name=truth\n\
+                    read_1\t10\t30\thomopolymer\n\
+                    # comment\n\
+                    read_1\t0\t5\n\
+                    read_2\t0\t100\ttandem_repeat\r\n";
+        let truth = parse_truth_bed(text).unwrap();
+        assert_eq!(
+            truth["read_1"],
+            vec![
+                (Interval::new(0, 5), DEFAULT_CATEGORY.to_string()),
+                (Interval::new(10, 30), "homopolymer".to_string())
+            ]
+        );
+        assert_eq!(truth["read_2"][0].1, "tandem_repeat");
+        assert!(parse_truth_bed("read_1\t10\n").is_err());
+        assert!(parse_truth_bed("read_1\t30\t10\n").is_err());
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let truth = vec![
+            (Interval::new(10, 30), "homopolymer".to_string()),
+            (Interval::new(50, 60), "dinucleotide".to_string()),
+        ];
+        // Covers all of the homopolymer and 2 of 10 dinucleotide bases, plus 10 false bases
+        let predicted = [Interval::new(5, 30), Interval::new(58, 63), Interval::new(80, 85)];
+        let mut evaluation = Evaluation::new(0.5);
+        evaluation.add_read(&truth, &predicted);
+
+        let homopolymer = &evaluation.categories["homopolymer"];
+        assert_eq!((homopolymer.detected_intervals, homopolymer.detected_bases), (1, 20));
+        let dinucleotide = &evaluation.categories["dinucleotide"];
+        assert_eq!((dinucleotide.detected_intervals, dinucleotide.detected_bases), (0, 2));
+        assert_eq!(evaluation.total.truth_bases, 30);
+        assert_eq!(evaluation.total.base_sensitivity(), 22.0 / 30.0);
+        assert_eq!(evaluation.total.interval_sensitivity(), 0.5);
+        assert_eq!(evaluation.base_precision(), 22.0 / 35.0);
+        assert_eq!(evaluation.interval_precision(), 2.0 / 3.0);
+
+        // A read without truth intervals only adds false positives
+        let mut other = Evaluation::new(0.5);
+        other.add_read(&[], &[Interval::new(0, 10)]);
+        evaluation.merge(&other);
+        assert_eq!(evaluation.reads, 2);
+        assert_eq!(evaluation.predicted_bases, 45);
+        assert_eq!(evaluation.total.truth_intervals, 2);
+    }
+}
@@ -0,0 +1,148 @@
+// FASTQ output
+//
+// Records are written as raw bytes: headers (including comments), sequences
+// and qualities are not required to be valid UTF-8 and are copied through
+// unchanged, so unmasked records are byte-identical to the input.
+
+use std::io::{self, Write};
+
+use crate::intervals::Interval;
+
+/// Masking metadata added to read headers as SAM-style tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderAnnotation {
+    /// Masked fraction and interval count (`mf:f`, `mi:i`)
+    Summary,
+    /// Interval count and list (`mi:i`, `mr:Z`)
+    Intervals,
+    /// All of the above
+    Full,
+}
+
+impl HeaderAnnotation {
+    /// Tags describing the masking of one read
+    ///
+    /// `len` is the length of the written read and `intervals` are masked intervals in its
+    /// coordinates. The interval list is written as comma-separated 0-based half-open
+    /// `start-end` pairs and left out if the read has no masked intervals.
+    pub fn tags(&self, masked_bases: usize, len: usize, intervals: &[Interval]) -> Vec<String> {
+        let mut tags = Vec::new();
+        if matches!(self, HeaderAnnotation::Summary | HeaderAnnotation::Full) {
+            let fraction = if len == 0 { 0.0 } else { masked_bases as f64 / len as f64 };
+            tags.push(format!("mf:f:{:.3}", fraction));
+        }
+        tags.push(format!("mi:i:{}", intervals.len()));
+        if matches!(self, HeaderAnnotation::Intervals | HeaderAnnotation::Full) && !intervals.is_empty() {
+            let list: Vec<String> = intervals
+                .iter()
+                .map(|iv| format!("{}-{}", iv.start, iv.end))
+                .collect();
+            tags.push(format!("mr:Z:{}", list.join(",")));
+        }
+        tags
+    }
+}
+
+/// Replace the comment of a header line with tags, separated by tabs as in SAM (and as
+/// expected by `minimap2 -y`, which copies the header comment into the alignment)
+///
+/// Comment fields that are SAM tags themselves (e.g. from `samtools fastq -T`) are kept,
+/// except for tags of the same name, which are replaced. Other comments, such as Illumina's
+/// `1:N:0:ACGT`, are dropped because they would not be valid SAM tags.
+pub fn append_tags(id: &[u8], tags: &[String]) -> Vec<u8> {
+    let is_space = |b: &u8| *b == b' ' || *b == b'\t';
+    let name_len = id.iter().position(is_space).unwrap_or(id.len());
+    let mut header = id[..name_len].to_vec();
+    let kept = id[name_len..]
+        .split(is_space)
+        .filter(|field| is_sam_tag(field))
+        .filter(|field| !tags.iter().any(|tag| tag.as_bytes()[..2] == field[..2]));
+    for field in kept.chain(tags.iter().map(|tag| tag.as_bytes())) {
+        header.push(b'\t');
+        header.extend_from_slice(field);
+    }
+    header
+}
+
+/// Whether a header comment field has the form of a SAM tag (`TG:T:value`)
+fn is_sam_tag(field: &[u8]) -> bool {
+    field.len() >= 5
+        && field[0].is_ascii_alphabetic()
+        && field[1].is_ascii_alphanumeric()
+        && field[2] == b':'
+        && b"AcCsSiIfZHB".contains(&field[3])
+        && field[4] == b':'
+}
+
+/// Write one FASTQ record
+///
+/// `id` is the full header line without the leading '@' and `plus` the content of the
+/// separator line after the '+' (usually empty).
+pub fn write_fastq<W: Write + ?Sized>(
+    writer: &mut W,
+    id: &[u8],
+    seq: &[u8],
+    plus: &[u8],
+    qual: &[u8],
+) -> io::Result<()> {
+    writer.write_all(b"@")?;
+    writer.write_all(id)?;
+    writer.write_all(b"\n")?;
+    writer.write_all(seq)?;
+    writer.write_all(b"\n+")?;
+    writer.write_all(plus)?;
+    writer.write_all(b"\n")?;
+    writer.write_all(qual)?;
+    writer.write_all(b"\n")
+}
+
+/// Content of the '+' line of a raw four-line FASTQ record (without the '+')
+pub fn plus_line(record: &[u8]) -> &[u8] {
+    let line = record.split(|&b| b == b'\n').nth(2).unwrap_or(b"+");
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    line.strip_prefix(b"+").unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use needletail::parse_fastx_reader;
+
+    #[test]
+    fn test_unmasked_records_round_trip() {
+        // Non-UTF-8 bytes in the header and quality, comments, and a repeated header on the '+' line
+        let input: &[u8] = b"@read1 comment\xff with\ttab\nACGTNACGT\n+\nIIII#\xfeIII\n\
+                             @read2\nACGT\n+read2\n!!!!\n";
+        let mut reader = parse_fastx_reader(input).unwrap();
+        let mut output = Vec::new();
+        while let Some(record) = reader.next() {
+            let record = record.unwrap();
+            let plus = plus_line(record.all());
+            write_fastq(&mut output, record.id(), &record.seq(), plus, record.qual().unwrap()).unwrap();
+        }
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_plus_line() {
+        assert_eq!(plus_line(b"@r1\nACGT\n+\nIIII"), b"");
+        assert_eq!(plus_line(b"@r1\r\nACGT\r\n+r1\r\nIIII"), b"r1");
+    }
+
+    #[test]
+    fn test_header_annotation() {
+        let intervals = [Interval::new(0, 10), Interval::new(20, 23)];
+        let tags = HeaderAnnotation::Full.tags(13, 40, &intervals);
+        assert_eq!(tags, vec!["mf:f:0.325", "mi:i:2", "mr:Z:0-10,20-23"]);
+        let summary = HeaderAnnotation::Summary.tags(0, 40, &[]);
+        assert_eq!(append_tags(b"read1", &summary), b"read1\tmf:f:0.000\tmi:i:0");
+        // Comments that are not SAM tags are dropped, so `minimap2 -y` copies valid tags only
+        assert_eq!(append_tags(b"read1 1:N:0:ACGT", &summary), b"read1\tmf:f:0.000\tmi:i:0");
+        // SAM tags in the comment are kept, and earlier masking tags replaced
+        assert_eq!(
+            append_tags(b"read1 BC:Z:ACGT\tmi:i:3 1:N:0", &summary),
+            b"read1\tBC:Z:ACGT\tmf:f:0.000\tmi:i:0"
+        );
+        assert_eq!(HeaderAnnotation::Intervals.tags(0, 40, &[]), vec!["mi:i:0"]);
+    }
+}
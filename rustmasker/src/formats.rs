@@ -0,0 +1,349 @@
+// Input and output file formats
+//
+// Input formats and compression are detected from the first bytes of the
+// stream (so piped input works); output formats follow the output file
+// extension.
+
+use std::io::{self, BufWriter, Read, Write};
+
+use flate2::read::MultiGzDecoder;
+use crate::bgzf::BgzfWriter;
+use gzp::{deflate::Gzip, par::compress::ParCompressBuilder, Compression as GzpCompression, ZWriter};
+
+/// Number of leading bytes inspected to detect the input format
+pub const SNIFF_LEN: usize = 64 * 1024;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: [u8; 3] = *b"BZh";
+const XZ_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const BAM_MAGIC: [u8; 4] = *b"BAM\x01";
+
+/// zstd levels for `--compression-level` 0-9, spread over zstd's 1-19 range
+const ZSTD_LEVELS: [i32; 10] = [0, 1, 3, 5, 7, 9, 12, 15, 17, 19];
+
+/// Stream compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    /// Blocked gzip (readable as gzip, supports random access)
+    Bgzf,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Detect the compression from the first bytes of a stream
+    /// BGZF is reported as gzip.
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if head.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if head.starts_with(&BZIP2_MAGIC) {
+            Compression::Bzip2
+        } else if head.starts_with(&XZ_MAGIC) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Compression implied by a file extension, None for unrecognised extensions
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "gz" => Some(Compression::Gzip),
+            "bgz" => Some(Compression::Bgzf),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    /// Codec level for a `--compression-level` of 1-9
+    /// gzip, BGZF, bzip2 and xz use the level directly; zstd levels are spread over 1-19.
+    pub fn codec_level(&self, level: u32) -> u32 {
+        let level = level.min(9);
+        match self {
+            Compression::Zstd => ZSTD_LEVELS[level as usize] as u32,
+            _ => level,
+        }
+    }
+}
+
+/// Wrap a reader in a decoder for compressed input
+pub fn decompress_reader(
+    compression: Compression,
+    reader: Box<dyn Read + Send>,
+) -> io::Result<Box<dyn Read + Send>> {
+    Ok(match compression {
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+        Compression::Gzip | Compression::Bgzf => Box::new(MultiGzDecoder::new(reader)),
+        Compression::None => reader,
+    })
+}
+
+/// An output stream that must be finished to be complete (e.g. to write a compression footer)
+pub trait FinishWrite: Write {
+    /// Flush buffered data and end the stream; nothing may be written afterwards
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl<W: Write> FinishWrite for BgzfWriter<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        BgzfWriter::finish(self)
+    }
+}
+
+/// A buffered encoder, and the function that ends its stream
+struct Encoder<E: Write> {
+    writer: BufWriter<E>,
+    end: fn(&mut E) -> io::Result<()>,
+}
+
+impl<E: Write> Encoder<E> {
+    fn new(encoder: E, end: fn(&mut E) -> io::Result<()>) -> Self {
+        Self { writer: BufWriter::new(encoder), end }
+    }
+}
+
+impl<E: Write> Write for Encoder<E> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.writer.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<E: Write> FinishWrite for Encoder<E> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        (self.end)(self.writer.get_mut())
+    }
+}
+
+/// Wrap a writer in a (multithreaded where supported) encoder
+///
+/// `level` is a `--compression-level` of 1-9. gzip and zstd use `threads` compression
+/// threads (all cores if None), BGZF uses the rayon thread pool; bzip2 and xz are
+/// single-threaded. The output is only complete once `finish` returns.
+pub fn compress_writer<W: Write + Send + 'static>(
+    writer: W,
+    compression: Compression,
+    level: u32,
+    threads: Option<usize>,
+) -> io::Result<Box<dyn FinishWrite>> {
+    let codec_level = compression.codec_level(level);
+    Ok(match compression {
+        Compression::None => Box::new(Encoder::new(writer, |writer| writer.flush())),
+        Compression::Gzip => {
+            let mut builder =
+                ParCompressBuilder::<Gzip>::new().compression_level(GzpCompression::new(codec_level));
+            if let Some(threads) = threads {
+                builder = builder
+                    .num_threads(threads)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+            }
+            Box::new(Encoder::new(builder.from_writer(writer), |encoder| {
+                encoder.finish().map_err(io::Error::other)
+            }))
+        }
+        Compression::Bgzf => Box::new(BgzfWriter::new(writer, codec_level)),
+        Compression::Zstd => {
+            let threads = match threads {
+                Some(threads) => threads,
+                None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            };
+            let mut encoder = zstd::stream::write::Encoder::new(writer, codec_level as i32)?;
+            if threads > 1 {
+                encoder.multithread(threads as u32)?;
+            }
+            Box::new(Encoder::new(encoder, |encoder| encoder.do_finish()))
+        }
+        Compression::Bzip2 => {
+            let encoder = bzip2::write::BzEncoder::new(writer, bzip2::Compression::new(codec_level));
+            Box::new(Encoder::new(encoder, |encoder| encoder.try_finish()))
+        }
+        Compression::Xz => {
+            let encoder = xz2::write::XzEncoder::new(writer, codec_level);
+            Box::new(Encoder::new(encoder, |encoder| encoder.try_finish()))
+        }
+    })
+}
+
+/// Sequence record format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceFormat {
+    /// FASTQ or FASTA
+    Fastx,
+    Sam,
+    Bam,
+}
+
+impl SequenceFormat {
+    /// Format implied by a file name, ignoring a compression extension (e.g. `.gz`)
+    /// Returns None for unrecognised extensions.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.to_ascii_lowercase();
+        let path = match Compression::from_path(&path) {
+            Some(_) => path.rsplit_once('.')?.0,
+            None => &path,
+        };
+        let extension = path.rsplit_once('.')?.1;
+        match extension {
+            "bam" => Some(SequenceFormat::Bam),
+            "sam" => Some(SequenceFormat::Sam),
+            "fastq" | "fq" | "fasta" | "fa" | "fna" => Some(SequenceFormat::Fastx),
+            _ => None,
+        }
+    }
+
+    /// Whether a file name has a FASTA extension, ignoring a compression extension
+    pub fn is_fasta_path(path: &str) -> bool {
+        let path = path.to_ascii_lowercase();
+        let path = match Compression::from_path(&path) {
+            Some(_) => path.rsplit_once('.').map_or("", |(stem, _)| stem),
+            None => &path,
+        };
+        matches!(path.rsplit_once('.'), Some((_, "fasta" | "fa" | "fna")))
+    }
+
+    /// Detect the format from the first bytes of a (possibly gzip/BGZF-compressed) stream
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(&BAM_MAGIC) {
+            // Uncompressed (or already decompressed) BAM
+            return SequenceFormat::Bam;
+        }
+        if is_gzip(head) {
+            let mut decompressed = Vec::new();
+            // The head is usually a truncated gzip stream, so decompression
+            // stops with an error after the available bytes
+            let _ = MultiGzDecoder::new(head)
+                .take(SNIFF_LEN as u64)
+                .read_to_end(&mut decompressed);
+            if decompressed.starts_with(&BAM_MAGIC) {
+                return SequenceFormat::Bam;
+            }
+            return Self::detect_text(&decompressed);
+        }
+        Self::detect_text(head)
+    }
+
+    /// Tell SAM from FASTQ/FASTA by the header line or the number of columns
+    fn detect_text(head: &[u8]) -> Self {
+        let first_line = head.split(|&b| b == b'\n').next().unwrap_or(&[]);
+        match first_line {
+            // SAM header lines: @HD, @SQ, @RG, @PG, @CO followed by a tab
+            [b'@', a, b, b'\t', ..] if a.is_ascii_uppercase() && b.is_ascii_uppercase() => {
+                SequenceFormat::Sam
+            }
+            [b'@' | b'>', ..] => SequenceFormat::Fastx,
+            // Headerless SAM has at least 11 tab-separated columns
+            _ if first_line.iter().filter(|&&b| b == b'\t').count() >= 10 => SequenceFormat::Sam,
+            _ => SequenceFormat::Fastx,
+        }
+    }
+}
+
+/// Whether a stream starts with the gzip (or BGZF) magic bytes
+pub fn is_gzip(head: &[u8]) -> bool {
+    head.starts_with(&GZIP_MAGIC)
+}
+
+/// Read up to [`SNIFF_LEN`] bytes for format detection
+/// The bytes are consumed; callers chain them back in front of the reader.
+pub fn read_head<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_detect_text_formats() {
+        assert_eq!(SequenceFormat::detect(b"@r1\nACGT\n+\nIIII\n"), SequenceFormat::Fastx);
+        assert_eq!(SequenceFormat::detect(b">r1\nACGT\n"), SequenceFormat::Fastx);
+        assert_eq!(SequenceFormat::detect(b"@HD\tVN:1.6\n"), SequenceFormat::Sam);
+        let record = b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII\n";
+        assert_eq!(SequenceFormat::detect(record), SequenceFormat::Sam);
+        assert_eq!(SequenceFormat::detect(b""), SequenceFormat::Fastx);
+    }
+
+    #[test]
+    fn test_detect_compressed_formats() {
+        assert_eq!(SequenceFormat::detect(&gzip(b"@r1\nACGT\n+\nIIII\n")), SequenceFormat::Fastx);
+        assert_eq!(SequenceFormat::detect(&gzip(b"@CO\ttest\n")), SequenceFormat::Sam);
+        // Only the start of the stream is available
+        let bam = gzip(b"BAM\x01\x00\x00\x00\x00\x00\x00\x00\x00");
+        assert_eq!(SequenceFormat::detect(&bam[..bam.len() - 4]), SequenceFormat::Bam);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(SequenceFormat::from_path("reads.bam"), Some(SequenceFormat::Bam));
+        assert_eq!(SequenceFormat::from_path("reads.SAM"), Some(SequenceFormat::Sam));
+        assert_eq!(SequenceFormat::from_path("reads.fq.gz"), Some(SequenceFormat::Fastx));
+        assert_eq!(SequenceFormat::from_path("reads.sam.zst"), Some(SequenceFormat::Sam));
+        assert_eq!(SequenceFormat::from_path("reads.txt"), None);
+        assert_eq!(SequenceFormat::from_path("reads"), None);
+        assert!(SequenceFormat::is_fasta_path("genome.FA.gz"));
+        assert!(!SequenceFormat::is_fasta_path("reads.fq.gz"));
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let data = b"@r1\nACGT\n+\nIIII\n".repeat(100);
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::Bzip2, Compression::Xz] {
+            let path = std::env::temp_dir().join(format!(
+                "rustmasker_compression_{}_{:?}",
+                std::process::id(),
+                compression
+            ));
+            let mut writer =
+                compress_writer(std::fs::File::create(&path).unwrap(), compression, 1, Some(2)).unwrap();
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+            drop(writer);
+
+            let mut reader: Box<dyn Read + Send> = Box::new(std::fs::File::open(&path).unwrap());
+            let head = read_head(&mut reader).unwrap();
+            assert_eq!(Compression::detect(&head), compression);
+            let mut decompressed = Vec::new();
+            let reader = Box::new(io::Cursor::new(head).chain(reader));
+            let mut reader = decompress_reader(compression, reader).unwrap();
+            reader.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, data);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compression_from_path_and_levels() {
+        assert_eq!(Compression::from_path("out.fastq.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_path("out.fq.BZ2"), Some(Compression::Bzip2));
+        assert_eq!(Compression::from_path("out.fastq"), None);
+        assert_eq!(Compression::from_path("genome.fa.bgz"), Some(Compression::Bgzf));
+        assert_eq!(Compression::Zstd.codec_level(1), 1);
+        assert_eq!(Compression::Zstd.codec_level(9), 19);
+        assert_eq!(Compression::Xz.codec_level(6), 6);
+    }
+}
@@ -0,0 +1,267 @@
+// Homopolymer run detection
+//
+// ONT reads in particular carry systematic errors in long single-base runs.
+// These helpers find runs of a single base (A, C, G or T, case-insensitive)
+// independently of the entropy scan, so they can be masked or just reported.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Bases tracked by the homopolymer detector, in index order
+pub const HOMOPOLYMER_BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// Map a base to its index in HOMOPOLYMER_BASES (None for N or invalid bases)
+#[inline]
+fn base_index(base: u8) -> Option<usize> {
+    match base {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' => Some(3),
+        _ => None,
+    }
+}
+
+/// A maximal run of a single base: [start, end) in read coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HomopolymerRun {
+    /// Uppercase base of the run (A, C, G or T)
+    pub base: u8,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl HomopolymerRun {
+    /// Length of the run in bases
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Runs are never empty, provided for API completeness
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+}
+
+/// Minimum run length per base (A, C, G, T) for a run to be detected
+///
+/// `None` disables detection for that base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinRunLength(pub [Option<usize>; 4]);
+
+impl MinRunLength {
+    /// Same minimum run length for all four bases
+    pub fn uniform(min_run: usize) -> Self {
+        MinRunLength([Some(min_run); 4])
+    }
+
+    /// Minimum run length for a base (None if the base is not detected)
+    pub fn get(&self, base: u8) -> Option<usize> {
+        base_index(base).and_then(|i| self.0[i])
+    }
+}
+
+/// Parse either a single length applied to all bases ("10") or a comma-separated
+/// list of per-base lengths ("A=12,C=8,G=8,T=12"). Bases not listed are not detected.
+impl FromStr for MinRunLength {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(min_run) = s.parse::<usize>() {
+            if min_run < 2 {
+                return Err(format!("minimum run length must be at least 2, got {}", min_run));
+            }
+            return Ok(MinRunLength::uniform(min_run));
+        }
+
+        let mut lengths = [None; 4];
+        for part in s.split(',') {
+            let (base, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected BASE=LENGTH, got '{}'", part))?;
+            let index = match base.trim().as_bytes() {
+                [b] => base_index(*b),
+                _ => None,
+            }
+            .ok_or_else(|| format!("invalid base '{}' (expected A, C, G or T)", base))?;
+            let min_run: usize = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid run length '{}' for base {}", value, base))?;
+            if min_run < 2 {
+                return Err(format!("minimum run length must be at least 2, got {}", min_run));
+            }
+            lengths[index] = Some(min_run);
+        }
+        Ok(MinRunLength(lengths))
+    }
+}
+
+impl fmt::Display for MinRunLength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = HOMOPOLYMER_BASES
+            .iter()
+            .zip(self.0.iter())
+            .filter_map(|(&base, len)| len.map(|l| format!("{}={}", base as char, l)))
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+/// Call `f` for every maximal run of A, C, G or T in the sequence (including runs of length 1)
+/// N and other non-ACGT bases break runs and are never reported.
+pub fn for_each_run<F: FnMut(HomopolymerRun)>(sequence: &[u8], mut f: F) {
+    let mut i = 0;
+    while i < sequence.len() {
+        let Some(index) = base_index(sequence[i]) else {
+            i += 1;
+            continue;
+        };
+        let start = i;
+        i += 1;
+        while i < sequence.len() && base_index(sequence[i]) == Some(index) {
+            i += 1;
+        }
+        f(HomopolymerRun {
+            base: HOMOPOLYMER_BASES[index],
+            start,
+            end: i,
+        });
+    }
+}
+
+/// Find homopolymer runs at least as long as the per-base minimum
+pub fn find_homopolymer_runs(sequence: &[u8], min_run: &MinRunLength) -> Vec<HomopolymerRun> {
+    let mut runs = Vec::new();
+    for_each_run(sequence, |run| {
+        if let Some(min_len) = min_run.get(run.base) {
+            if run.len() >= min_len {
+                runs.push(run);
+            }
+        }
+    });
+    runs
+}
+
+/// Per-base histogram of homopolymer run lengths
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunLengthHistogram {
+    /// counts[base][len] = number of runs of that base with that length
+    counts: [Vec<u64>; 4],
+}
+
+impl RunLengthHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a single run
+    pub fn add_run(&mut self, run: &HomopolymerRun) {
+        if let Some(index) = base_index(run.base) {
+            let counts = &mut self.counts[index];
+            if counts.len() <= run.len() {
+                counts.resize(run.len() + 1, 0);
+            }
+            counts[run.len()] += 1;
+        }
+    }
+
+    /// Record every run in a sequence
+    pub fn add_sequence(&mut self, sequence: &[u8]) {
+        for_each_run(sequence, |run| self.add_run(&run));
+    }
+
+    /// Merge another histogram into this one (used to combine per-thread results)
+    pub fn merge(&mut self, other: &RunLengthHistogram) {
+        for (mine, theirs) in self.counts.iter_mut().zip(other.counts.iter()) {
+            if mine.len() < theirs.len() {
+                mine.resize(theirs.len(), 0);
+            }
+            for (m, t) in mine.iter_mut().zip(theirs.iter()) {
+                *m += t;
+            }
+        }
+    }
+
+    /// Number of runs of `base` with exactly `len` bases
+    pub fn count(&self, base: u8, len: usize) -> u64 {
+        base_index(base)
+            .and_then(|i| self.counts[i].get(len).copied())
+            .unwrap_or(0)
+    }
+
+    /// Non-zero entries as (run length → count) for one base
+    pub fn to_map(&self, base: u8) -> BTreeMap<usize, u64> {
+        base_index(base)
+            .map(|i| {
+                self.counts[i]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &c)| c > 0)
+                    .map(|(len, &c)| (len, c))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_runs_respects_per_base_minimum() {
+        let sequence = b"ACGTAAAAAAGGGGCCCCCCCCTN";
+        let min_run: MinRunLength = "A=6,C=8".parse().unwrap();
+        let runs = find_homopolymer_runs(sequence, &min_run);
+
+        assert_eq!(
+            runs,
+            vec![
+                HomopolymerRun { base: b'A', start: 4, end: 10 },
+                HomopolymerRun { base: b'C', start: 14, end: 22 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_runs_are_case_insensitive_and_broken_by_n() {
+        let sequence = b"aaaAAANAAAA";
+        let runs = find_homopolymer_runs(sequence, &MinRunLength::uniform(4));
+        assert_eq!(
+            runs,
+            vec![
+                HomopolymerRun { base: b'A', start: 0, end: 6 },
+                HomopolymerRun { base: b'A', start: 7, end: 11 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_min_run_length_parsing() {
+        assert_eq!("10".parse::<MinRunLength>().unwrap(), MinRunLength::uniform(10));
+        let parsed: MinRunLength = "A=12, T=9".parse().unwrap();
+        assert_eq!(parsed.0, [Some(12), None, None, Some(9)]);
+        assert_eq!(parsed.to_string(), "A=12,T=9");
+        assert!("N=5".parse::<MinRunLength>().is_err());
+        assert!("A=1".parse::<MinRunLength>().is_err());
+        assert!("A".parse::<MinRunLength>().is_err());
+    }
+
+    #[test]
+    fn test_histogram() {
+        let sequence = b"AAACCTTTTAAA";
+        let mut histogram = RunLengthHistogram::new();
+        histogram.add_sequence(sequence);
+        assert_eq!(histogram.count(b'A', 3), 2);
+        assert_eq!(histogram.count(b'C', 2), 1);
+        assert_eq!(histogram.count(b'T', 4), 1);
+
+        let mut other = RunLengthHistogram::new();
+        other.add_sequence(b"AAA");
+        histogram.merge(&other);
+        assert_eq!(histogram.to_map(b'A').get(&3), Some(&3));
+    }
+}
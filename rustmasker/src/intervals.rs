@@ -0,0 +1,95 @@
+// Masked intervals
+//
+// All detectors describe what they would mask as half-open [start, end)
+// intervals in read coordinates. Intervals are combined and post-processed
+// before being applied to the read.
+
+use crate::mask_range;
+
+/// A half-open interval [start, end) in read coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Interval {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Interval {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Length of the interval in bases
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+}
+
+/// Sort intervals and merge those that overlap or touch
+pub fn merge_intervals(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.retain(|iv| !iv.is_empty());
+    intervals.sort_unstable();
+
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for iv in intervals {
+        match merged.last_mut() {
+            Some(last) if iv.start <= last.end => last.end = last.end.max(iv.end),
+            _ => merged.push(iv),
+        }
+    }
+    merged
+}
+
+/// Extend the last interval if `[start, end)` overlaps or touches it, otherwise append
+/// Used by sliding-window scans, which produce intervals in increasing start order.
+#[inline]
+pub fn push_interval(intervals: &mut Vec<Interval>, start: usize, end: usize) {
+    match intervals.last_mut() {
+        Some(last) if start <= last.end => last.end = last.end.max(end),
+        _ => intervals.push(Interval::new(start, end)),
+    }
+}
+
+/// Total number of bases covered by (non-overlapping) intervals
+pub fn total_length(intervals: &[Interval]) -> usize {
+    intervals.iter().map(|iv| iv.len()).sum()
+}
+
+/// Mask all intervals in place (bases → 'N', qualities → '#')
+/// Returns the number of bases changed to N. Quality may be empty (FASTA input).
+pub fn apply_intervals(sequence: &mut [u8], quality: &mut [u8], intervals: &[Interval]) -> usize {
+    intervals
+        .iter()
+        .map(|iv| mask_range(sequence, quality, iv.start, iv.end))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_intervals() {
+        let merged = merge_intervals(vec![
+            Interval::new(10, 20),
+            Interval::new(0, 5),
+            Interval::new(5, 8),
+            Interval::new(15, 30),
+            Interval::new(40, 40),
+        ]);
+        assert_eq!(merged, vec![Interval::new(0, 8), Interval::new(10, 30)]);
+        assert_eq!(total_length(&merged), 28);
+    }
+
+    #[test]
+    fn test_apply_intervals_without_quality() {
+        let mut seq = b"ACGTNACGT".to_vec();
+        let mut qual = Vec::new();
+        let masked = apply_intervals(&mut seq, &mut qual, &[Interval::new(2, 6)]);
+        assert_eq!(masked, 3);
+        assert_eq!(&seq, b"ACNNNNCGT");
+    }
+}
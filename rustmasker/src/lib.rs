@@ -1,6 +1,71 @@
 // Shared library for rustmasker
 use std::collections::HashMap;
 
+pub mod alignment;
+pub mod batch;
+pub mod bench;
+pub mod bgzf;
+pub mod calibrate;
+pub mod compare;
+pub mod config;
+pub mod decompress;
+pub mod evaluate;
+pub mod fastx;
+pub mod formats;
+pub mod homopolymer;
+pub mod intervals;
+pub mod outfmt;
+pub mod pipeline;
+pub mod progress;
+pub mod sdust;
+pub mod simulate;
+pub mod stats;
+pub mod tails;
+
+use intervals::{apply_intervals, push_interval, Interval};
+
+/// Entropy calculation implementation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntropyMethod {
+    /// Array-based for k ≤ 7, HashMap-based for k > 7
+    Auto,
+    /// Array-based entropy tracker
+    Array,
+    /// HashMap-based k-mer counts
+    Hashmap,
+}
+
+/// How entropy masking treats reads shorter than the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShortReadPolicy {
+    /// Entropy of the whole read decides whether to mask all or nothing (BBMask-compatible)
+    WholeRead,
+    /// Slide a window of half the read length (at least k + 1 bases) with the selected
+    /// method, masking only the low-complexity part of the read. Reads of k bases or fewer
+    /// have no such window and are never masked.
+    ShrinkWindow,
+    /// Never mask reads shorter than the window
+    Never,
+    /// Always mask reads shorter than the window
+    Always,
+}
+
+/// Mask positions [start, end) in place (bases → 'N', qualities → '#')
+/// Returns the number of bases changed to N. Quality may be empty (FASTA input).
+pub fn mask_range(sequence: &mut [u8], quality: &mut [u8], start: usize, end: usize) -> usize {
+    let mut masked = 0;
+    for pos in start..end {
+        if sequence[pos] != b'N' {
+            sequence[pos] = b'N';
+            masked += 1;
+        }
+        if pos < quality.len() {
+            quality[pos] = b'#';
+        }
+    }
+    masked
+}
+
 /// Encode a k-mer into a u32 using 2 bits per base (A=00, C=01, G=10, T=11)
 /// Returns None if the k-mer contains N or invalid bases
 /// Maximum k-mer size: 15 bases (30 bits / 2 bits per base)
@@ -93,27 +158,45 @@ pub fn remove_kmer(kmer_counts: &mut HashMap<u32, usize>, kmer: &[u8]) {
     }
 }
 
+/// Whole-sequence entropy, used when the sequence is shorter than the window
+fn whole_sequence_intervals(sequence: &[u8], entropy_threshold: f64, k: usize) -> Vec<Interval> {
+    let seq_len = sequence.len();
+    let kmer_counts = get_kmers(sequence, k);
+    let total_kmers = if seq_len >= k { seq_len - k + 1 } else { 0 };
+    let entropy = shannon_entropy(&kmer_counts, total_kmers);
+
+    if entropy < entropy_threshold && seq_len > 0 {
+        vec![Interval::new(0, seq_len)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Copy a read and mask the given intervals
+fn masked_copy(sequence: &[u8], quality: &[u8], intervals: &[Interval]) -> (Vec<u8>, Vec<u8>) {
+    let mut masked_seq = sequence.to_vec();
+    let mut masked_qual = quality.to_vec();
+    apply_intervals(&mut masked_seq, &mut masked_qual, intervals);
+    (masked_seq, masked_qual)
+}
+
 /// Mask low-complexity regions in a sequence based on entropy
 /// Matches BBMask behavior: masks entire window ranges when low entropy is detected
 pub fn mask_sequence(sequence: &[u8], quality: &[u8], window: usize, entropy_threshold: f64, k: usize) -> (Vec<u8>, Vec<u8>) {
+    let intervals = entropy_intervals(sequence, window, entropy_threshold, k);
+    masked_copy(sequence, quality, &intervals)
+}
+
+/// Find low-complexity intervals based on entropy (HashMap-based k-mer counts)
+/// Returns the merged union of all windows with entropy below the threshold, so
+/// masking these intervals gives the same result as mask_sequence()
+pub fn entropy_intervals(sequence: &[u8], window: usize, entropy_threshold: f64, k: usize) -> Vec<Interval> {
     let seq_len = sequence.len();
-    let mut masked_seq = sequence.to_vec();
-    let mut masked_qual = quality.to_vec();
+    let mut intervals = Vec::new();
 
     if seq_len < window {
         // If sequence is shorter than window, calculate entropy for the whole sequence
-        let kmer_counts = get_kmers(sequence, k);
-        let total_kmers = if seq_len >= k { seq_len - k + 1 } else { 0 };
-        let entropy = shannon_entropy(&kmer_counts, total_kmers);
-
-        if entropy < entropy_threshold {
-            // Mask entire sequence
-            for i in 0..seq_len {
-                masked_seq[i] = b'N';
-                masked_qual[i] = b'#';
-            }
-        }
-        return (masked_seq, masked_qual);
+        return whole_sequence_intervals(sequence, entropy_threshold, k);
     }
 
     // BBMask-style sliding window: mask entire window range when low entropy detected
@@ -126,11 +209,7 @@ pub fn mask_sequence(sequence: &[u8], quality: &[u8], window: usize, entropy_thr
     for i in 0..seq_len {
         // Window extends from [window_start, window_end)
         // Build window up to position i (inclusive)
-        let window_start = if i + 1 >= window {
-            i + 1 - window
-        } else {
-            0
-        };
+        let window_start = (i + 1).saturating_sub(window);
         let window_end = i + 1;
 
         // Only check entropy once window is full (has reached target size)
@@ -167,14 +246,11 @@ pub fn mask_sequence(sequence: &[u8], quality: &[u8], window: usize, entropy_thr
         // If entropy is below threshold, mask the entire window range
         // This matches BBMask's behavior of masking complete windows
         if entropy < entropy_threshold {
-            for pos in window_start..window_end {
-                masked_seq[pos] = b'N';
-                masked_qual[pos] = b'#';
-            }
+            push_interval(&mut intervals, window_start, window_end);
         }
     }
 
-    (masked_seq, masked_qual)
+    intervals
 }
 
 // ============================================================================
@@ -219,9 +295,9 @@ impl ArrayEntropyTracker {
         // Precalculate entropy table: entropy[count] = (count/window_kmers) * log2(count/window_kmers)
         // This matches BBMask's approach
         let mut entropy_table = vec![0.0; window_kmers + 2];
-        for count in 1..entropy_table.len() {
+        for (count, entry) in entropy_table.iter_mut().enumerate().skip(1) {
             let p = count as f64 / window_kmers as f64;
-            entropy_table[count] = p * p.log2();
+            *entry = p * p.log2();
         }
 
         // Normalization factor to convert entropy to 0-1 scale
@@ -337,25 +413,25 @@ pub fn mask_sequence_array(
     entropy_threshold: f64,
     k: usize
 ) -> (Vec<u8>, Vec<u8>) {
+    let intervals = entropy_intervals_array(sequence, window, entropy_threshold, k);
+    masked_copy(sequence, quality, &intervals)
+}
+
+/// Find low-complexity intervals using the array-based entropy tracker
+/// Array-based counterpart of entropy_intervals()
+pub fn entropy_intervals_array(
+    sequence: &[u8],
+    window: usize,
+    entropy_threshold: f64,
+    k: usize
+) -> Vec<Interval> {
     let seq_len = sequence.len();
-    let mut masked_seq = sequence.to_vec();
-    let mut masked_qual = quality.to_vec();
+    let mut intervals = Vec::new();
 
     if seq_len < window {
         // If sequence is shorter than window, calculate entropy for the whole sequence
         // Fall back to HashMap for short sequences (not worth the array overhead)
-        let kmer_counts = get_kmers(sequence, k);
-        let total_kmers = if seq_len >= k { seq_len - k + 1 } else { 0 };
-        let entropy = shannon_entropy(&kmer_counts, total_kmers);
-
-        if entropy < entropy_threshold {
-            // Mask entire sequence
-            for i in 0..seq_len {
-                masked_seq[i] = b'N';
-                masked_qual[i] = b'#';
-            }
-        }
-        return (masked_seq, masked_qual);
+        return whole_sequence_intervals(sequence, entropy_threshold, k);
     }
 
     // Use array-based tracker for sliding window
@@ -364,11 +440,7 @@ pub fn mask_sequence_array(
 
     for i in 0..seq_len {
         // Window extends from [window_start, window_end)
-        let window_start = if i + 1 >= window {
-            i + 1 - window
-        } else {
-            0
-        };
+        let window_start = (i + 1).saturating_sub(window);
         let window_end = i + 1;
 
         // Only check entropy once window is full
@@ -409,14 +481,75 @@ pub fn mask_sequence_array(
 
         // If entropy is below threshold, mask the entire window range
         if entropy < entropy_threshold {
-            for pos in window_start..window_end {
-                masked_seq[pos] = b'N';
-                masked_qual[pos] = b'#';
-            }
+            push_interval(&mut intervals, window_start, window_end);
         }
     }
 
-    (masked_seq, masked_qual)
+    intervals
+}
+
+/// Entropy of every full window along a sequence, with the selected entropy method
+/// Value i is the entropy of window [i, i + window), as compared against the threshold by
+/// entropy_mask_intervals(); empty if the sequence is shorter than the window
+pub fn entropy_track(sequence: &[u8], window: usize, k: usize, method: EntropyMethod) -> Vec<f64> {
+    if sequence.len() < window {
+        return Vec::new();
+    }
+    match method {
+        EntropyMethod::Auto if k <= 7 => entropy_track_array(sequence, window, k),
+        EntropyMethod::Auto => entropy_track_hashmap(sequence, window, k),
+        EntropyMethod::Array => entropy_track_array(sequence, window, k),
+        EntropyMethod::Hashmap => entropy_track_hashmap(sequence, window, k),
+    }
+}
+
+/// Entropy track from HashMap-based k-mer counts (see entropy_intervals())
+fn entropy_track_hashmap(sequence: &[u8], window: usize, k: usize) -> Vec<f64> {
+    let seq_len = sequence.len();
+    let total_kmers = if window >= k { window - k + 1 } else { 0 };
+    let mut kmer_counts = get_kmers(&sequence[..window], k);
+
+    let mut track = Vec::with_capacity(seq_len - window + 1);
+    track.push(shannon_entropy(&kmer_counts, total_kmers));
+    for window_start in 1..=seq_len - window {
+        if window >= k {
+            remove_kmer(&mut kmer_counts, &sequence[window_start - 1..window_start - 1 + k]);
+            add_kmer(&mut kmer_counts, &sequence[window_start + window - k..window_start + window]);
+        }
+        track.push(shannon_entropy(&kmer_counts, total_kmers));
+    }
+    track
+}
+
+/// Entropy track from the array-based entropy tracker (see entropy_intervals_array())
+fn entropy_track_array(sequence: &[u8], window: usize, k: usize) -> Vec<f64> {
+    if window <= k {
+        // The array tracker needs at least two k-mers per window
+        return entropy_track_hashmap(sequence, window, k);
+    }
+    let seq_len = sequence.len();
+    let mut tracker = ArrayEntropyTracker::new(k, window);
+    tracker.clear();
+    for j in 0..=window - k {
+        if let Some(kmer_code) = encode_kmer(&sequence[j..j + k]) {
+            tracker.add_kmer(kmer_code);
+        }
+    }
+
+    let mut track = Vec::with_capacity(seq_len - window + 1);
+    track.push(tracker.entropy());
+    for window_start in 1..=seq_len - window {
+        // Slide forward by 1 base: remove the k-mer that exited, add the one that entered
+        if let Some(kmer_code) = encode_kmer(&sequence[window_start - 1..window_start - 1 + k]) {
+            tracker.remove_kmer(kmer_code);
+        }
+        let entering_kmer_pos = window_start + window - k;
+        if let Some(kmer_code) = encode_kmer(&sequence[entering_kmer_pos..entering_kmer_pos + k]) {
+            tracker.add_kmer(kmer_code);
+        }
+        track.push(tracker.entropy());
+    }
+    track
 }
 
 /// Automatically choose between array-based and HashMap-based masking based on k
@@ -441,6 +574,40 @@ pub fn mask_sequence_auto(
     }
 }
 
+/// Find low-complexity intervals with the selected entropy method
+/// `EntropyMethod::Auto` follows the same rule as mask_sequence_auto(), and
+/// reads shorter than the window are handled according to `short_reads`
+pub fn entropy_mask_intervals(
+    sequence: &[u8],
+    window: usize,
+    entropy_threshold: f64,
+    k: usize,
+    method: EntropyMethod,
+    short_reads: ShortReadPolicy,
+) -> Vec<Interval> {
+    let seq_len = sequence.len();
+    if seq_len < window {
+        match short_reads {
+            ShortReadPolicy::WholeRead => {}
+            ShortReadPolicy::ShrinkWindow if seq_len > k => {
+                // Several windows per read, each normalized by its own k-mer count
+                let window = (seq_len / 2).max(k + 1);
+                return entropy_mask_intervals(sequence, window, entropy_threshold, k, method, short_reads);
+            }
+            ShortReadPolicy::ShrinkWindow | ShortReadPolicy::Never => return Vec::new(),
+            ShortReadPolicy::Always if seq_len > 0 => return vec![Interval::new(0, seq_len)],
+            ShortReadPolicy::Always => return Vec::new(),
+        }
+    }
+
+    match method {
+        EntropyMethod::Auto if k <= 7 => entropy_intervals_array(sequence, window, entropy_threshold, k),
+        EntropyMethod::Auto => entropy_intervals(sequence, window, entropy_threshold, k),
+        EntropyMethod::Array => entropy_intervals_array(sequence, window, entropy_threshold, k),
+        EntropyMethod::Hashmap => entropy_intervals(sequence, window, entropy_threshold, k),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -617,6 +784,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_short_read_policies() {
+        let low = b"ATATATATATATATATATAT";
+        let high = b"ACGTTGCAGCTAGCATCGAC";
+        let run = |seq: &[u8], policy| entropy_mask_intervals(seq, 80, 0.7, 5, EntropyMethod::Auto, policy);
+
+        assert_eq!(run(low, ShortReadPolicy::WholeRead), vec![Interval::new(0, 20)]);
+        assert!(run(high, ShortReadPolicy::WholeRead).is_empty());
+        assert_eq!(run(low, ShortReadPolicy::ShrinkWindow), vec![Interval::new(0, 20)]);
+        assert!(run(high, ShortReadPolicy::ShrinkWindow).is_empty());
+        assert!(run(low, ShortReadPolicy::Never).is_empty());
+        assert_eq!(run(high, ShortReadPolicy::Always), vec![Interval::new(0, 20)]);
+
+        // Shrinking masks only the low-complexity half; the whole read's entropy is too high
+        let mixed = b"ATATATATATATATATATATACGTTGCAGCTAGCATCGAC";
+        assert!(run(mixed, ShortReadPolicy::WholeRead).is_empty());
+        assert_eq!(run(mixed, ShortReadPolicy::ShrinkWindow), vec![Interval::new(0, 28)]);
+
+        // Reads with no complete k-mer: whole-read entropy is 0, shrinking has nothing to score
+        assert_eq!(run(b"ACG", ShortReadPolicy::WholeRead), vec![Interval::new(0, 3)]);
+        assert!(run(b"ACG", ShortReadPolicy::ShrinkWindow).is_empty());
+        // Nor does a read of k bases, which has no window of k + 1
+        assert!(run(b"AAAAA", ShortReadPolicy::ShrinkWindow).is_empty());
+        assert_eq!(run(b"AAAAAA", ShortReadPolicy::ShrinkWindow), vec![Interval::new(0, 6)]);
+    }
+
     #[test]
     fn test_mask_sequence_array_low_complexity() {
         let sequence = b"AAAAAAAAAA";
@@ -649,4 +842,32 @@ mod tests {
         let masked_count = masked_seq.iter().filter(|&&b| b == b'N').count();
         assert_eq!(masked_count, 26);
     }
+
+    #[test]
+    fn test_entropy_track_matches_intervals() {
+        let sequence = b"ACGTTGCANNACGGATCCAAAAAAAAAAAAAAAAAAAAAAACACACACACACACACAGGTACCATGACTTGACNACGT";
+        let (window, k, threshold) = (20, 3, 0.7);
+        let track = entropy_track(sequence, window, k, EntropyMethod::Array);
+        assert_eq!(track.len(), sequence.len() - window + 1);
+        assert!(track.iter().all(|&e| (0.0..=1.0).contains(&e)));
+
+        let mut intervals = Vec::new();
+        for (start, &entropy) in track.iter().enumerate() {
+            if entropy < threshold {
+                push_interval(&mut intervals, start, start + window);
+            }
+        }
+        assert!(!intervals.is_empty());
+        assert_eq!(intervals, entropy_intervals_array(sequence, window, threshold, k));
+        assert!(entropy_track(b"ACGT", window, k, EntropyMethod::Auto).is_empty());
+
+        // The HashMap-based track agrees with the array-based one
+        let hashmap = entropy_track(sequence, window, k, EntropyMethod::Hashmap);
+        assert_eq!(hashmap.len(), track.len());
+        assert!(hashmap.iter().zip(&track).all(|(a, b)| (a - b).abs() < 1e-9));
+
+        // A window no larger than k falls back to the HashMap-based track
+        assert_eq!(entropy_track(sequence, 5, 5, EntropyMethod::Array).len(), sequence.len() - 4);
+        assert_eq!(entropy_track(sequence, 3, 5, EntropyMethod::Array).len(), sequence.len() - 2);
+    }
 }
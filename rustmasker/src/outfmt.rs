@@ -0,0 +1,163 @@
+// Interval and track output formats
+//
+// Instead of masked reads, the masked intervals of each sequence can be
+// written the way NCBI dustmasker does with `-outfmt interval`, `acclist` or
+// `fasta`, so rustmasker can replace it in database-building scripts. As in
+// dustmasker, interval coordinates are 0-based with inclusive ends.
+//
+// Per-window entropy values can also be written as a bedGraph track for
+// genome browsers, to see the entropy curve behind the mask.
+
+use std::io::{self, Write};
+
+use crate::intervals::Interval;
+
+/// Line width of lowercase-masked FASTA output (as in dustmasker)
+pub const FASTA_LINE_WIDTH: usize = 60;
+
+/// dustmasker output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DustFormat {
+    /// `>header` followed by one `start - end` line per interval
+    Interval,
+    /// One `>id<TAB>start<TAB>end` line per interval
+    Acclist,
+    /// FASTA with masked bases in lowercase
+    Fasta,
+}
+
+impl DustFormat {
+    /// Write the masked intervals of one sequence
+    ///
+    /// `header` is the full header line without the leading '@' or '>'; `intervals` are
+    /// half-open and sorted.
+    pub fn write_record<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+        header: &[u8],
+        seq: &[u8],
+        intervals: &[Interval],
+    ) -> io::Result<()> {
+        match self {
+            DustFormat::Interval => {
+                writer.write_all(b">")?;
+                writer.write_all(header)?;
+                writer.write_all(b"\n")?;
+                for iv in intervals {
+                    writeln!(writer, "{} - {}", iv.start, iv.end - 1)?;
+                }
+            }
+            DustFormat::Acclist => {
+                let id = header.split(|b| b.is_ascii_whitespace()).next().unwrap_or(header);
+                for iv in intervals {
+                    writer.write_all(b">")?;
+                    writer.write_all(id)?;
+                    writeln!(writer, "\t{}\t{}", iv.start, iv.end - 1)?;
+                }
+            }
+            DustFormat::Fasta => {
+                writer.write_all(b">")?;
+                writer.write_all(header)?;
+                writer.write_all(b"\n")?;
+                for line in lowercase_masked(seq, intervals).chunks(FASTA_LINE_WIDTH) {
+                    writer.write_all(line)?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copy of a sequence with the bases in `intervals` in lowercase and all others in uppercase
+pub fn lowercase_masked(seq: &[u8], intervals: &[Interval]) -> Vec<u8> {
+    let mut masked = seq.to_ascii_uppercase();
+    for iv in intervals {
+        let end = iv.end.min(masked.len());
+        if iv.start < end {
+            masked[iv.start..end].make_ascii_lowercase();
+        }
+    }
+    masked
+}
+
+/// Write the bedGraph track definition line
+pub fn write_bedgraph_header<W: Write + ?Sized>(writer: &mut W, window: usize, k: usize) -> io::Result<()> {
+    writeln!(
+        writer,
+        "track type=bedGraph name=entropy description=\"rustmasker entropy (window {}, k {})\"",
+        window, k
+    )
+}
+
+/// Write the entropy track of one sequence as bedGraph lines, keeping every `step`-th window
+///
+/// `track[i]` is the entropy of window [i, i + window) (see `entropy_track()`); each kept
+/// value is written for the `step` bases around the window center, so consecutive lines
+/// tile the sequence. `step` must be between 1 and the window size.
+pub fn write_bedgraph<W: Write + ?Sized>(
+    writer: &mut W,
+    chrom: &[u8],
+    track: &[f64],
+    window: usize,
+    step: usize,
+) -> io::Result<()> {
+    for (i, entropy) in track.iter().enumerate().step_by(step) {
+        let start = i + window / 2 - step / 2;
+        writer.write_all(chrom)?;
+        writeln!(writer, "\t{}\t{}\t{:.4}", start, start + step, entropy)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(format: DustFormat, seq: &[u8], intervals: &[Interval]) -> String {
+        let mut output = Vec::new();
+        format.write_record(&mut output, b"chr1 test sequence", seq, intervals).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_interval_and_acclist() {
+        let intervals = [Interval::new(0, 10), Interval::new(20, 35)];
+        assert_eq!(
+            write(DustFormat::Interval, b"", &intervals),
+            ">chr1 test sequence\n0 - 9\n20 - 34\n"
+        );
+        assert_eq!(
+            write(DustFormat::Acclist, b"", &intervals),
+            ">chr1\t0\t9\n>chr1\t20\t34\n"
+        );
+        // Unmasked sequences are listed in interval format only
+        assert_eq!(write(DustFormat::Interval, b"", &[]), ">chr1 test sequence\n");
+        assert_eq!(write(DustFormat::Acclist, b"", &[]), "");
+    }
+
+    #[test]
+    fn test_lowercase_fasta() {
+        let seq = b"acgtACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTA";
+        let output = write(DustFormat::Fasta, seq, &[Interval::new(60, 64)]);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].len(), FASTA_LINE_WIDTH);
+        assert!(lines[1].starts_with("ACGTACGT"));
+        assert_eq!(lines[2], "acgtA");
+    }
+
+    #[test]
+    fn test_bedgraph() {
+        let track = [0.5, 0.25, 0.125, 1.0, 0.75];
+        let mut output = Vec::new();
+        write_bedgraph(&mut output, b"chr1", &track, 10, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "chr1\t4\t6\t0.5000\nchr1\t6\t8\t0.1250\nchr1\t8\t10\t0.7500\n"
+        );
+        let mut output = Vec::new();
+        write_bedgraph(&mut output, b"chr1", &track, 10, 1).unwrap();
+        assert!(String::from_utf8(output).unwrap().starts_with("chr1\t5\t6\t0.5000\n"));
+    }
+}
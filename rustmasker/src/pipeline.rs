@@ -0,0 +1,394 @@
+// Composable masking pipeline
+//
+// A pipeline runs several detectors over a read and combines their interval
+// sets. Each combined interval remembers which detectors contributed to it,
+// so reports can say why a region was masked.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::homopolymer::{find_homopolymer_runs, MinRunLength};
+use crate::intervals::{merge_intervals, Interval};
+use crate::sdust::sdust_intervals;
+use crate::tails::{find_poly_x_tails, TailConfig};
+use crate::{entropy_mask_intervals, EntropyMethod, ShortReadPolicy};
+
+/// A masking criterion that reports low-complexity intervals for a read
+#[derive(Debug, Clone)]
+pub enum Detector {
+    /// Shannon entropy of k-mers in a sliding window (BBMask-compatible)
+    Entropy {
+        window: usize,
+        threshold: f64,
+        k: usize,
+        method: EntropyMethod,
+        short_reads: ShortReadPolicy,
+    },
+    /// Symmetric DUST score (sdust/dustmasker-compatible)
+    Sdust { window: usize, threshold: i32 },
+    /// Homopolymer runs at least the per-base minimum length
+    Homopolymer { min_run: MinRunLength },
+    /// Poly-X tails at the read ends
+    PolyX { config: TailConfig },
+}
+
+impl Detector {
+    /// Short name used in reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            Detector::Entropy { .. } => "entropy",
+            Detector::Sdust { .. } => "sdust",
+            Detector::Homopolymer { .. } => "homopolymer",
+            Detector::PolyX { .. } => "poly-x",
+        }
+    }
+
+    /// Sorted, merged intervals this detector would mask
+    pub fn detect(&self, sequence: &[u8]) -> Vec<Interval> {
+        match self {
+            Detector::Entropy { window, threshold, k, method, short_reads } => {
+                entropy_mask_intervals(sequence, *window, *threshold, *k, *method, *short_reads)
+            }
+            Detector::Sdust { window, threshold } => sdust_intervals(sequence, *window, *threshold),
+            Detector::Homopolymer { min_run } => find_homopolymer_runs(sequence, min_run)
+                .iter()
+                .map(|run| Interval::new(run.start, run.end))
+                .collect(),
+            Detector::PolyX { config } => {
+                let (five_prime, three_prime) = find_poly_x_tails(sequence, config);
+                five_prime
+                    .into_iter()
+                    .chain(three_prime)
+                    .map(|tail| Interval::new(tail.start, tail.end))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// How detector results are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombineRule {
+    /// Mask bases flagged by any detector
+    Union,
+    /// Mask bases flagged by every detector
+    Intersection,
+    /// Mask bases flagged by at least this many detectors
+    MinOverlap(usize),
+}
+
+impl FromStr for CombineRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "union" => Ok(CombineRule::Union),
+            "intersection" => Ok(CombineRule::Intersection),
+            other => {
+                let n = other
+                    .strip_prefix("min-overlap:")
+                    .ok_or_else(|| {
+                        format!("invalid rule '{}' (expected union, intersection or min-overlap:N)", other)
+                    })?
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid detector count in '{}'", other))?;
+                if n < 1 {
+                    return Err("min-overlap requires at least 1 detector".to_string());
+                }
+                Ok(CombineRule::MinOverlap(n))
+            }
+        }
+    }
+}
+
+impl fmt::Display for CombineRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombineRule::Union => write!(f, "union"),
+            CombineRule::Intersection => write!(f, "intersection"),
+            CombineRule::MinOverlap(n) => write!(f, "min-overlap:{}", n),
+        }
+    }
+}
+
+/// A combined interval with a bitmask of the detectors that flagged any part of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabeledInterval {
+    pub interval: Interval,
+    /// Bit i is set if detector i (in pipeline order) contributed
+    pub sources: u32,
+}
+
+/// Combine per-detector interval sets (each sorted and merged)
+///
+/// Coverage is evaluated on the elementary segments between interval boundaries;
+/// a segment is kept when at least `required` detectors cover it. Adjacent kept
+/// segments are merged and their source masks OR-ed together.
+pub fn combine_intervals(per_detector: &[Vec<Interval>], required: usize) -> Vec<LabeledInterval> {
+    let mut boundaries: Vec<usize> = per_detector
+        .iter()
+        .flatten()
+        .flat_map(|iv| [iv.start, iv.end])
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut cursors = vec![0usize; per_detector.len()];
+    let mut combined: Vec<LabeledInterval> = Vec::new();
+
+    for segment in boundaries.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        let mut sources = 0u32;
+        for (d, intervals) in per_detector.iter().enumerate() {
+            let cursor = &mut cursors[d];
+            while *cursor < intervals.len() && intervals[*cursor].end <= start {
+                *cursor += 1;
+            }
+            if *cursor < intervals.len() && intervals[*cursor].start <= start {
+                sources |= 1 << d;
+            }
+        }
+        if sources == 0 || (sources.count_ones() as usize) < required {
+            continue;
+        }
+        match combined.last_mut() {
+            Some(last) if last.interval.end == start => {
+                last.interval.end = end;
+                last.sources |= sources;
+            }
+            _ => combined.push(LabeledInterval {
+                interval: Interval::new(start, end),
+                sources,
+            }),
+        }
+    }
+
+    combined
+}
+
+/// Post-processing applied to combined intervals before masking
+///
+/// Steps run in this order so that short fragments separated by small gaps
+/// survive as one interval:
+/// 1. merge intervals separated by at most `max_gap` unmasked bases
+/// 2. drop (unmask) intervals shorter than `min_len`
+/// 3. extend each interval by `padding` bases on both sides (clamped to the read)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PostProcess {
+    pub min_len: usize,
+    pub max_gap: usize,
+    pub padding: usize,
+}
+
+impl PostProcess {
+    /// True if post-processing leaves intervals unchanged
+    pub fn is_noop(&self) -> bool {
+        self.min_len <= 1 && self.max_gap == 0 && self.padding == 0
+    }
+
+    /// Apply post-processing to sorted, non-overlapping intervals of a read of length `seq_len`
+    pub fn apply(&self, intervals: Vec<LabeledInterval>, seq_len: usize) -> Vec<LabeledInterval> {
+        if self.is_noop() {
+            return intervals;
+        }
+
+        let mut merged: Vec<LabeledInterval> = Vec::with_capacity(intervals.len());
+        for li in intervals {
+            match merged.last_mut() {
+                Some(last) if li.interval.start <= last.interval.end + self.max_gap => {
+                    last.interval.end = last.interval.end.max(li.interval.end);
+                    last.sources |= li.sources;
+                }
+                _ => merged.push(li),
+            }
+        }
+
+        merged.retain(|li| li.interval.len() >= self.min_len);
+
+        let mut padded: Vec<LabeledInterval> = Vec::with_capacity(merged.len());
+        for mut li in merged {
+            li.interval.start = li.interval.start.saturating_sub(self.padding);
+            li.interval.end = (li.interval.end + self.padding).min(seq_len);
+            match padded.last_mut() {
+                Some(last) if li.interval.start <= last.interval.end => {
+                    last.interval.end = last.interval.end.max(li.interval.end);
+                    last.sources |= li.sources;
+                }
+                _ => padded.push(li),
+            }
+        }
+        padded
+    }
+}
+
+/// A set of detectors, the rule used to combine them and post-processing of the result
+#[derive(Debug, Clone)]
+pub struct MaskPipeline {
+    pub detectors: Vec<Detector>,
+    pub rule: CombineRule,
+    pub post: PostProcess,
+}
+
+impl MaskPipeline {
+    /// Create a pipeline (at most 32 detectors) without post-processing
+    pub fn new(detectors: Vec<Detector>, rule: CombineRule) -> Self {
+        assert!(detectors.len() <= 32, "a pipeline supports at most 32 detectors");
+        Self {
+            detectors,
+            rule,
+            post: PostProcess::default(),
+        }
+    }
+
+    /// Set post-processing applied to the combined intervals
+    pub fn with_post_process(mut self, post: PostProcess) -> Self {
+        self.post = post;
+        self
+    }
+
+    /// Number of detectors that must flag a base for it to be masked
+    fn required(&self) -> usize {
+        match self.rule {
+            CombineRule::Union => 1,
+            CombineRule::Intersection => self.detectors.len(),
+            CombineRule::MinOverlap(n) => n,
+        }
+    }
+
+    /// Run all detectors over a read, combine their intervals and post-process the result
+    pub fn run(&self, sequence: &[u8]) -> Vec<LabeledInterval> {
+        let per_detector: Vec<Vec<Interval>> = self
+            .detectors
+            .iter()
+            .map(|detector| merge_intervals(detector.detect(sequence)))
+            .collect();
+        let combined = combine_intervals(&per_detector, self.required());
+        self.post.apply(combined, sequence.len())
+    }
+
+    /// Source mask of the detectors with this name (e.g. "entropy")
+    pub fn source_mask(&self, name: &str) -> u32 {
+        self.detectors
+            .iter()
+            .enumerate()
+            .filter(|(_, detector)| detector.name() == name)
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// Names of the detectors set in a source mask, joined with '+'
+    pub fn source_names(&self, sources: u32) -> String {
+        self.detectors
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| sources & (1 << i) != 0)
+            .map(|(_, detector)| detector.name())
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ivs(pairs: &[(usize, usize)]) -> Vec<Interval> {
+        pairs.iter().map(|&(s, e)| Interval::new(s, e)).collect()
+    }
+
+    #[test]
+    fn test_combine_rules() {
+        let per_detector = vec![ivs(&[(0, 10), (20, 30)]), ivs(&[(5, 25)]), ivs(&[(8, 12)])];
+
+        let union = combine_intervals(&per_detector, 1);
+        assert_eq!(union.len(), 1);
+        assert_eq!(union[0].interval, Interval::new(0, 30));
+        assert_eq!(union[0].sources, 0b111);
+
+        let two: Vec<(Interval, u32)> = combine_intervals(&per_detector, 2)
+            .iter()
+            .map(|li| (li.interval, li.sources))
+            .collect();
+        assert_eq!(two, vec![(Interval::new(5, 12), 0b111), (Interval::new(20, 25), 0b011)]);
+
+        let all = combine_intervals(&per_detector, 3);
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].interval, Interval::new(8, 10));
+    }
+
+    #[test]
+    fn test_pipeline_records_sources() {
+        let pipeline = MaskPipeline::new(
+            vec![
+                Detector::Entropy {
+                    window: 20,
+                    threshold: 0.5,
+                    k: 3,
+                    method: EntropyMethod::Auto,
+                    short_reads: ShortReadPolicy::WholeRead,
+                },
+                Detector::Homopolymer { min_run: MinRunLength::uniform(8) },
+            ],
+            "union".parse().unwrap(),
+        );
+        let sequence = b"ACGTAGCTAGCATCGATCAGCTAGCTAGCCCCCCCCGATCGA";
+        let intervals = pipeline.run(sequence);
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(intervals[0].interval, Interval::new(28, 36));
+        assert_eq!(pipeline.source_names(intervals[0].sources), "homopolymer");
+        assert_eq!(pipeline.source_mask("homopolymer"), 0b10);
+        assert_eq!(intervals[0].sources & pipeline.source_mask("entropy"), 0);
+    }
+
+    #[test]
+    fn test_homopolymer_masking() {
+        let pipeline = MaskPipeline::new(
+            vec![Detector::Homopolymer { min_run: MinRunLength::uniform(4) }],
+            CombineRule::Union,
+        );
+        let mut seq = b"AAACCTTTTAAA".to_vec();
+        let mut qual = vec![b'I'; seq.len()];
+        let intervals = pipeline.run(&seq);
+        assert_eq!(intervals.len(), 1);
+        let masked: usize = intervals
+            .iter()
+            .map(|li| crate::mask_range(&mut seq, &mut qual, li.interval.start, li.interval.end))
+            .sum();
+        assert_eq!(masked, 4);
+        assert_eq!(&seq, b"AAACCNNNNAAA");
+        assert_eq!(&qual, b"IIIII####III");
+    }
+
+    #[test]
+    fn test_post_process() {
+        let labeled = |pairs: &[(usize, usize, u32)]| -> Vec<LabeledInterval> {
+            pairs
+                .iter()
+                .map(|&(s, e, sources)| LabeledInterval { interval: Interval::new(s, e), sources })
+                .collect()
+        };
+        let intervals = labeled(&[(2, 4, 0b01), (6, 8, 0b10), (20, 22, 0b01), (40, 50, 0b01)]);
+
+        // Gap merging joins the first two fragments, which then survive min_len
+        let post = PostProcess { min_len: 5, max_gap: 2, padding: 0 };
+        assert_eq!(post.apply(intervals.clone(), 60), labeled(&[(2, 8, 0b11), (40, 50, 0b01)]));
+
+        // Padding is clamped to the read and padded intervals are merged
+        let post = PostProcess { min_len: 0, max_gap: 0, padding: 3 };
+        assert_eq!(
+            post.apply(intervals.clone(), 52),
+            labeled(&[(0, 11, 0b11), (17, 25, 0b01), (37, 52, 0b01)])
+        );
+
+        assert!(PostProcess::default().is_noop());
+        assert_eq!(PostProcess::default().apply(intervals.clone(), 60), intervals);
+    }
+
+    #[test]
+    fn test_combine_rule_parsing() {
+        assert_eq!("intersection".parse::<CombineRule>(), Ok(CombineRule::Intersection));
+        assert_eq!("min-overlap:2".parse::<CombineRule>(), Ok(CombineRule::MinOverlap(2)));
+        assert_eq!(CombineRule::MinOverlap(2).to_string(), "min-overlap:2");
+        assert!("min-overlap:0".parse::<CombineRule>().is_err());
+        assert!("majority".parse::<CombineRule>().is_err());
+    }
+}
@@ -0,0 +1,249 @@
+// Progress reporting
+//
+// With `--progress`, long runs report the reads and bases processed so far,
+// the masked fraction and the throughput at a fixed interval. Reports come
+// from a thread of their own, so they keep coming while a large chunk is
+// masked or a slow output is written. Input bytes are counted behind the data
+// the parser has consumed (in compressed bytes, for compressed input), so for
+// an input file of known size the fraction consumed gives an estimated
+// completion time.
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Reader counting the bytes read through it
+pub struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, count: Arc<AtomicU64>) -> Self {
+        Self { inner, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Counts of one input, shared with the reporting thread
+struct ProgressState {
+    label: String,
+    start: Instant,
+    /// Input bytes consumed by the parser
+    bytes: Arc<AtomicU64>,
+    /// Size of the input file, if known (not for stdin or pipes)
+    total_bytes: Option<u64>,
+    /// Reads and bases taken from the input
+    reads: AtomicU64,
+    bases: AtomicU64,
+    /// Bases of the reads masked so far, and how many of them were masked
+    processed_bases: AtomicU64,
+    masked_bases: AtomicU64,
+}
+
+impl ProgressState {
+    /// Fraction of the input consumed, if its size is known
+    fn fraction(&self) -> Option<f64> {
+        let total = self.total_bytes.filter(|&total| total > 0)?;
+        Some((self.bytes.load(Ordering::Relaxed) as f64 / total as f64).min(1.0))
+    }
+
+    fn line(&self) -> String {
+        let reads = self.reads.load(Ordering::Relaxed);
+        let bases = self.bases.load(Ordering::Relaxed);
+        // The masked fraction of the bases masked so far, applied to all bases read
+        let processed = self.processed_bases.load(Ordering::Relaxed);
+        let masked = self.masked_bases.load(Ordering::Relaxed);
+        let masked = if processed == 0 { 0 } else { (masked as f64 * bases as f64 / processed as f64) as u64 };
+        progress_line(&self.label, reads, bases, masked, self.start.elapsed(), self.fraction())
+    }
+}
+
+/// Progress of one input, reported every interval by a background thread
+pub struct Progress {
+    state: Arc<ProgressState>,
+    reporter: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Progress {
+    /// Progress of an input; with an interval, a progress line is written to stderr every
+    /// interval until the progress is dropped
+    pub fn new(label: &str, interval: Option<Duration>, total_bytes: Option<u64>) -> Self {
+        let state = Arc::new(ProgressState {
+            label: label.to_string(),
+            start: Instant::now(),
+            bytes: Arc::new(AtomicU64::new(0)),
+            total_bytes,
+            reads: AtomicU64::new(0),
+            bases: AtomicU64::new(0),
+            processed_bases: AtomicU64::new(0),
+            masked_bases: AtomicU64::new(0),
+        });
+        let reporter = interval.map(|interval| {
+            let (stop, stopped) = channel();
+            let state = Arc::clone(&state);
+            let handle = thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    eprintln!("Progress: {}", state.line());
+                }
+            });
+            (stop, handle)
+        });
+        Self { state, reporter }
+    }
+
+    /// Progress of an input file (or stdin), taking the total size from the file metadata
+    pub fn for_input(path: Option<&str>, interval: Option<Duration>) -> Self {
+        let total_bytes = path
+            .and_then(|path| std::fs::metadata(path).ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len());
+        Self::new(path.unwrap_or("-"), interval, total_bytes)
+    }
+
+    /// Counter of input bytes consumed by the parser, to be shared with a [`CountingReader`]
+    /// (or a decompressing reader)
+    pub fn counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.state.bytes)
+    }
+
+    /// Count a read taken from the input
+    pub fn record_read(&self, len: usize) {
+        self.state.reads.fetch_add(1, Ordering::Relaxed);
+        self.state.bases.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Set the bases masked so far and how many of them were masked
+    pub fn record_masked(&self, bases: u64, masked_bases: u64) {
+        self.state.processed_bases.store(bases, Ordering::Relaxed);
+        self.state.masked_bases.store(masked_bases, Ordering::Relaxed);
+    }
+
+    /// Current progress line
+    pub fn line(&self) -> String {
+        self.state.line()
+    }
+
+    /// Final line for the input
+    pub fn summary(&self, reads: u64, bases: u64, masked_bases: u64) -> String {
+        progress_line(&self.state.label, reads, bases, masked_bases, self.state.start.elapsed(), None)
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some((stop, handle)) = self.reporter.take() {
+            let _ = stop.send(());
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Progress line: counts, masked fraction, throughput and (given the fraction of the
+/// input consumed) the estimated time remaining
+pub fn progress_line(
+    label: &str,
+    reads: u64,
+    bases: u64,
+    masked_bases: u64,
+    elapsed: Duration,
+    fraction: Option<f64>,
+) -> String {
+    let seconds = elapsed.as_secs_f64().max(1e-9);
+    let masked = if bases == 0 { 0.0 } else { 100.0 * masked_bases as f64 / bases as f64 };
+    let mut line = format!(
+        "{}: {} reads, {}bp, {:.2}% masked, {:.0} reads/s, {}bp/s, {} elapsed",
+        label,
+        reads,
+        si(bases as f64),
+        masked,
+        reads as f64 / seconds,
+        si(bases as f64 / seconds),
+        format_duration(elapsed)
+    );
+    if let Some(fraction) = fraction {
+        line.push_str(&format!(", {:.1}% of input", 100.0 * fraction));
+        if fraction > 0.0 {
+            let remaining = Duration::from_secs_f64(seconds * (1.0 - fraction) / fraction);
+            line.push_str(&format!(", ETA {}", format_duration(remaining)));
+        }
+    }
+    line
+}
+
+/// Number with an SI prefix (e.g. 1.50 G)
+fn si(value: f64) -> String {
+    let prefixes = [(1e12, "T"), (1e9, "G"), (1e6, "M"), (1e3, "k")];
+    match prefixes.iter().find(|(scale, _)| value >= *scale) {
+        Some((scale, prefix)) => format!("{:.2} {}", value / scale, prefix),
+        None => format!("{:.0} ", value),
+    }
+}
+
+/// Duration as hours, minutes and seconds (e.g. 1h02m, 3m05s, 12s)
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        assert_eq!(format_duration(Duration::from_secs(12)), "12s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+
+        let line = progress_line("reads.fq", 2000, 3_000_000, 300_000, Duration::from_secs(10), Some(0.25));
+        assert_eq!(
+            line,
+            "reads.fq: 2000 reads, 3.00 Mbp, 10.00% masked, 200 reads/s, 300.00 kbp/s, 10s elapsed, \
+             25.0% of input, ETA 30s"
+        );
+        let line = progress_line("-", 0, 0, 0, Duration::ZERO, None);
+        assert_eq!(line, "-: 0 reads, 0 bp, 0.00% masked, 0 reads/s, 0 bp/s, 0s elapsed");
+
+        let count = Arc::new(AtomicU64::new(0));
+        let mut reader = CountingReader::new(&b"ACGTACGT"[..], Arc::clone(&count));
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn test_progress_counts() {
+        let progress = Progress::new("reads.fq", None, Some(1000));
+        progress.counter().store(250, Ordering::Relaxed);
+        for _ in 0..4 {
+            progress.record_read(100);
+        }
+        // Half of the bases masked so far were masked; the fraction covers all bases read
+        progress.record_masked(200, 100);
+        let line = progress.line();
+        assert!(line.starts_with("reads.fq: 4 reads, 400 bp, 50.00% masked,"), "{}", line);
+        assert!(line.contains("25.0% of input"), "{}", line);
+
+        // The reporting thread stops when the progress is dropped
+        let progress = Progress::new("-", Some(Duration::from_millis(1)), None);
+        thread::sleep(Duration::from_millis(5));
+        drop(progress);
+    }
+}
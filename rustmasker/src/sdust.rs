@@ -0,0 +1,248 @@
+// Symmetric DUST (sdust)
+//
+// Port of Heng Li's sdust (https://github.com/lh3/sdust), which gives output
+// nearly identical to NCBI dustmasker. Triplets are scored by how often they
+// repeat within a window; "perfect intervals" whose score exceeds the
+// threshold are reported. The bookkeeping mirrors sdust.c closely so that
+// results are identical.
+
+use std::collections::VecDeque;
+
+use crate::intervals::Interval;
+
+/// Word length (triplets)
+const SD_WLEN: usize = 3;
+/// Number of distinct triplets
+const SD_WTOT: usize = 1 << (SD_WLEN << 1);
+/// Mask for the 2-bit encoded triplet
+const SD_WMSK: usize = SD_WTOT - 1;
+
+/// Default sdust window size
+pub const SDUST_DEFAULT_WINDOW: usize = 64;
+/// Default sdust score threshold
+pub const SDUST_DEFAULT_THRESHOLD: i32 = 20;
+
+#[derive(Debug, Clone, Copy)]
+struct PerfectInterval {
+    start: usize,
+    finish: usize,
+    r: i32,
+    l: i32,
+}
+
+/// Window state shared between the sdust helper functions
+struct SdustState {
+    /// Triplets in the current window
+    w: VecDeque<usize>,
+    /// Perfect intervals, sorted by descending start then ascending finish
+    p: Vec<PerfectInterval>,
+    /// Merged output intervals
+    res: Vec<Interval>,
+    /// Triplet counts over the whole window (cw) and its suffix of length `l` (cv)
+    cw: [i32; SD_WTOT],
+    cv: [i32; SD_WTOT],
+    rw: i32,
+    rv: i32,
+    l: usize,
+}
+
+#[inline]
+fn nt4(base: u8) -> usize {
+    match base {
+        b'A' | b'a' => 0,
+        b'C' | b'c' => 1,
+        b'G' | b'g' => 2,
+        b'T' | b't' => 3,
+        _ => 4,
+    }
+}
+
+impl SdustState {
+    fn new() -> Self {
+        Self {
+            w: VecDeque::new(),
+            p: Vec::new(),
+            res: Vec::new(),
+            cw: [0; SD_WTOT],
+            cv: [0; SD_WTOT],
+            rw: 0,
+            rv: 0,
+            l: 0,
+        }
+    }
+
+    fn shift_window(&mut self, t: usize, threshold: i32, window: usize) {
+        if self.w.len() + SD_WLEN > window {
+            let s = self.w.pop_front().unwrap();
+            self.cw[s] -= 1;
+            self.rw -= self.cw[s];
+            if self.l > self.w.len() {
+                self.l -= 1;
+                self.cv[s] -= 1;
+                self.rv -= self.cv[s];
+            }
+        }
+        self.w.push_back(t);
+        self.l += 1;
+        self.rw += self.cw[t];
+        self.cw[t] += 1;
+        self.rv += self.cv[t];
+        self.cv[t] += 1;
+        if self.cv[t] * 10 > threshold << 1 {
+            loop {
+                let s = self.w[self.w.len() - self.l];
+                self.cv[s] -= 1;
+                self.rv -= self.cv[s];
+                self.l -= 1;
+                if s == t {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn save_masked_regions(&mut self, start: usize) {
+        let Some(p) = self.p.last().copied() else {
+            return;
+        };
+        if p.start >= start {
+            return;
+        }
+        let mut saved = false;
+        if let Some(last) = self.res.last_mut() {
+            // Overlapping with or adjacent to the previous interval
+            if p.start <= last.end {
+                saved = true;
+                last.end = last.end.max(p.finish);
+            }
+        }
+        if !saved {
+            self.res.push(Interval::new(p.start, p.finish));
+        }
+        // Remove perfect intervals that have fallen out of the window
+        while self.p.last().is_some_and(|p| p.start < start) {
+            self.p.pop();
+        }
+    }
+
+    fn find_perfect(&mut self, threshold: i32, start: usize) {
+        let mut c = self.cv;
+        let mut r = self.rv;
+        let mut max_r = 0;
+        let mut max_l = 0;
+        let w_len = self.w.len();
+
+        for i in (0..w_len - self.l).rev() {
+            let t = self.w[i];
+            r += c[t];
+            c[t] += 1;
+            let new_r = r;
+            let new_l = (w_len - i - 1) as i32;
+            if new_r * 10 > threshold * new_l {
+                // Find the insertion position
+                let mut j = 0;
+                while j < self.p.len() && self.p[j].start >= i + start {
+                    let p = &self.p[j];
+                    if max_r == 0 || p.r * max_l > max_r * p.l {
+                        max_r = p.r;
+                        max_l = p.l;
+                    }
+                    j += 1;
+                }
+                if max_r == 0 || new_r * max_l >= max_r * new_l {
+                    max_r = new_r;
+                    max_l = new_l;
+                    self.p.insert(
+                        j,
+                        PerfectInterval {
+                            start: i + start,
+                            finish: w_len + (SD_WLEN - 1) + start,
+                            r: new_r,
+                            l: new_l,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Find low-complexity intervals with the symmetric DUST algorithm
+///
+/// # Arguments
+/// * `window` - Window size in bases (sdust default: 64)
+/// * `threshold` - Score threshold (sdust default: 20)
+///
+/// Returns merged [start, end) intervals. N and other non-ACGT bases split the
+/// sequence into independently scored pieces, as in sdust. Like sdust.c, the
+/// triplet window is not reset at an N, which can place an interval end past the
+/// end of the sequence; intervals are clamped to the sequence length.
+pub fn sdust_intervals(sequence: &[u8], window: usize, threshold: i32) -> Vec<Interval> {
+    let mut state = SdustState::new();
+    let mut l = 0usize; // length of the current contiguous A/C/G/T stretch
+    let mut t = 0usize; // current triplet
+
+    for i in 0..=sequence.len() {
+        let b = if i < sequence.len() { nt4(sequence[i]) } else { 4 };
+        if b < 4 {
+            l += 1;
+            t = ((t << 2) | b) & SD_WMSK;
+            if l >= SD_WLEN {
+                // Start of the current window
+                let start = l.saturating_sub(window) + (i + 1 - l);
+                state.save_masked_regions(start);
+                state.shift_window(t, threshold, window);
+                if state.rw * 10 > state.l as i32 * threshold {
+                    state.find_perfect(threshold, start);
+                }
+            }
+        } else {
+            // N or the end of the sequence: flush unsaved perfect intervals
+            let mut start = (l + 1).saturating_sub(window) + (i + 1 - l);
+            while !state.p.is_empty() {
+                state.save_masked_regions(start);
+                start += 1;
+            }
+            l = 0;
+            t = 0;
+        }
+    }
+
+    let seq_len = sequence.len();
+    for iv in state.res.iter_mut() {
+        iv.end = iv.end.min(seq_len);
+    }
+    state.res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdust_masks_homopolymer() {
+        let mut sequence = b"ACGTTGCAGCTAGCATCGACTAGCTACGGATCAGCATCAGCTAGC".to_vec();
+        let run_start = sequence.len();
+        sequence.extend(std::iter::repeat_n(b'A', 40));
+        sequence.extend_from_slice(b"GCTAGCATGCATCGACTAGCATCGGCATGCACGTAC");
+
+        let intervals = sdust_intervals(&sequence, SDUST_DEFAULT_WINDOW, SDUST_DEFAULT_THRESHOLD);
+        assert_eq!(intervals.len(), 1);
+        assert!(intervals[0].start <= run_start);
+        assert!(intervals[0].end >= run_start + 40);
+    }
+
+    #[test]
+    fn test_sdust_ignores_high_complexity() {
+        let sequence = b"ACGTTGCAGCTAGCATCGACTAGCTACGGATCAGCATCAGCTAGCGATCGTAGCTAGTCGATGCATGCA";
+        assert!(sdust_intervals(sequence, SDUST_DEFAULT_WINDOW, SDUST_DEFAULT_THRESHOLD).is_empty());
+    }
+
+    #[test]
+    fn test_sdust_dinucleotide_repeat_split_by_n() {
+        let sequence = b"CACACACACACACACACACACACACACACANCACACACACACACACACACACACACACACA";
+        let intervals = sdust_intervals(sequence, SDUST_DEFAULT_WINDOW, SDUST_DEFAULT_THRESHOLD);
+        // Both pieces are masked but the N is not (sdust.c reports 31-89 for the second piece)
+        assert_eq!(intervals, vec![Interval::new(0, 30), Interval::new(31, 61)]);
+    }
+}
@@ -0,0 +1,384 @@
+// Synthetic reads for testing and benchmarking
+//
+// Reads of low (homopolymers, di- and trinucleotide repeats), medium (short
+// repeat units) and high (random) complexity, as generated by
+// scripts/generate_test_data.py. High-complexity reads can carry embedded
+// homopolymers, short tandem repeats and runs of N, and every low-complexity
+// stretch is recorded as a truth interval to score masking against. A small
+// seeded generator keeps the output reproducible across platforms without
+// extra dependencies.
+
+use crate::intervals::Interval;
+
+/// Seeded pseudo-random number generator (SplitMix64)
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in [0, n)
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * n as f64) as usize
+    }
+
+    /// Uniform float in [0, 1)
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform integer in [low, high]
+    pub fn range(&mut self, low: usize, high: usize) -> usize {
+        low + self.below(high - low + 1)
+    }
+
+    pub fn base(&mut self) -> u8 {
+        b"ACGT"[self.below(4)]
+    }
+
+    pub fn bases(&mut self, n: usize) -> Vec<u8> {
+        (0..n).map(|_| self.base()).collect()
+    }
+
+    /// Standard normal deviate (Box-Muller)
+    pub fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.unit();
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * self.unit()).cos()
+    }
+
+    /// Poisson-distributed count with the given mean
+    pub fn poisson(&mut self, mean: f64) -> usize {
+        if mean <= 0.0 {
+            return 0;
+        }
+        if mean > 30.0 {
+            return (mean + mean.sqrt() * self.normal()).round().max(0.0) as usize;
+        }
+        let limit = (-mean).exp();
+        let mut product = self.unit();
+        let mut count = 0;
+        while product > limit {
+            product *= self.unit();
+            count += 1;
+        }
+        count
+    }
+}
+
+/// Sequence complexity class of a simulated read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Complexity {
+    /// Homopolymer, dinucleotide or trinucleotide repeat
+    Low,
+    /// Repeat of a random 4-8 base unit
+    Medium,
+    /// Random sequence
+    High,
+}
+
+/// Kind of low-complexity stretch in a simulated read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Homopolymer,
+    Dinucleotide,
+    Trinucleotide,
+    /// Tandem repeat of a unit of 4 or more bases
+    TandemRepeat,
+    /// Run of N
+    NRun,
+}
+
+impl Feature {
+    /// Feature of a tandem repeat of the given unit, by its primitive period (so that a unit
+    /// such as `AAA` or `ATAT` is a homopolymer or a dinucleotide repeat)
+    pub fn of_unit(unit: &[u8]) -> Self {
+        match primitive_period(unit) {
+            1 => Feature::Homopolymer,
+            2 => Feature::Dinucleotide,
+            3 => Feature::Trinucleotide,
+            _ => Feature::TandemRepeat,
+        }
+    }
+
+    /// Category name, as written in truth BED files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Homopolymer => "homopolymer",
+            Feature::Dinucleotide => "dinucleotide",
+            Feature::Trinucleotide => "trinucleotide",
+            Feature::TandemRepeat => "tandem_repeat",
+            Feature::NRun => "n_run",
+        }
+    }
+}
+
+/// Read length distribution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthModel {
+    /// Uniform within ±10% of the mean (as scripts/generate_test_data.py)
+    Uniform,
+    /// All reads of the same length (Illumina-like)
+    Fixed,
+    /// Log-normal with a long tail (ONT-like)
+    LogNormal,
+}
+
+/// Shape (sigma) of the log-normal length distribution
+const LOG_NORMAL_SIGMA: f64 = 0.8;
+
+impl LengthModel {
+    /// Draw a read length with the given mean (at least 1)
+    pub fn sample(&self, rng: &mut Rng, mean: usize) -> usize {
+        let len = match self {
+            LengthModel::Uniform => (mean as f64 * (0.9 + 0.2 * rng.unit())) as usize,
+            LengthModel::Fixed => mean,
+            LengthModel::LogNormal => {
+                let mu = (mean as f64).ln() - LOG_NORMAL_SIGMA * LOG_NORMAL_SIGMA / 2.0;
+                (mu + LOG_NORMAL_SIGMA * rng.normal()).exp() as usize
+            }
+        };
+        len.max(1)
+    }
+}
+
+/// Embedded features of high-complexity reads, as rates per kilobase
+#[derive(Debug, Clone, Default)]
+pub struct Embedding {
+    pub homopolymers: f64,
+    /// Short tandem repeats with units of 2-6 bases
+    pub tandem_repeats: f64,
+    pub n_runs: f64,
+    /// Length range (inclusive) of homopolymers and tandem repeats
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Length range (inclusive) of runs of N
+    pub min_n_length: usize,
+    pub max_n_length: usize,
+}
+
+/// A simulated read and its low-complexity stretches
+#[derive(Debug, Clone)]
+pub struct SimulatedRead {
+    pub seq: Vec<u8>,
+    /// Half-open, sorted and non-overlapping
+    pub truth: Vec<(Interval, Feature)>,
+}
+
+/// Parse an inclusive length range `MIN-MAX` (or a single length), with 1 <= MIN <= MAX
+pub fn parse_length_range(s: &str) -> Result<(usize, usize), String> {
+    let (min, max) = s.split_once('-').unwrap_or((s, s));
+    let parse = |v: &str| v.trim().parse::<usize>().map_err(|_| format!("invalid length range '{}'", s));
+    let (min, max) = (parse(min)?, parse(max)?);
+    if min == 0 || min > max {
+        return Err(format!("invalid length range '{}' (expected MIN-MAX with 1 <= MIN <= MAX)", s));
+    }
+    Ok((min, max))
+}
+
+/// Length of the shortest unit whose repeats make up `unit` (e.g. 2 for `ATAT`)
+fn primitive_period(unit: &[u8]) -> usize {
+    (1..unit.len())
+        .filter(|&period| unit.len().is_multiple_of(period))
+        .find(|&period| unit.chunks(period).all(|chunk| chunk == &unit[..period]))
+        .unwrap_or(unit.len())
+}
+
+/// Repeat a unit to the given length
+pub fn repeat_unit(unit: &[u8], len: usize) -> Vec<u8> {
+    unit.iter().copied().cycle().take(len).collect()
+}
+
+/// Repeat unit of a read of the given complexity (None for random sequence)
+fn read_unit(rng: &mut Rng, complexity: Complexity) -> Option<Vec<u8>> {
+    match complexity {
+        Complexity::Low => Some(match rng.below(3) {
+            0 => vec![rng.base()],
+            1 => [b"AT", b"CG", b"AC", b"GT", b"AG", b"CT"][rng.below(6)].to_vec(),
+            _ => rng.bases(3),
+        }),
+        Complexity::Medium => {
+            let unit_len = rng.range(4, 8);
+            Some(rng.bases(unit_len))
+        }
+        Complexity::High => None,
+    }
+}
+
+/// Random sequence of the given complexity
+pub fn sequence(rng: &mut Rng, complexity: Complexity, len: usize) -> Vec<u8> {
+    match read_unit(rng, complexity) {
+        Some(unit) => repeat_unit(&unit, len),
+        None => rng.bases(len),
+    }
+}
+
+/// Simulate a read of the given complexity and length
+///
+/// Low- and medium-complexity reads are a single repeat, recorded as one truth interval over
+/// the whole read. High-complexity reads are random sequence with embedded features placed
+/// at random, non-overlapping positions (features running past the end are cut short).
+pub fn simulate_read(rng: &mut Rng, complexity: Complexity, len: usize, embedding: &Embedding) -> SimulatedRead {
+    if let Some(unit) = read_unit(rng, complexity) {
+        let truth = if len > 0 { vec![(Interval::new(0, len), Feature::of_unit(&unit))] } else { Vec::new() };
+        return SimulatedRead { seq: repeat_unit(&unit, len), truth };
+    }
+
+    let mut seq = rng.bases(len);
+    let kb = len as f64 / 1000.0;
+    let mut features = Vec::new();
+    for _ in 0..rng.poisson(embedding.homopolymers * kb) {
+        features.push((rng.below(len), Feature::Homopolymer));
+    }
+    for _ in 0..rng.poisson(embedding.tandem_repeats * kb) {
+        features.push((rng.below(len), Feature::TandemRepeat));
+    }
+    for _ in 0..rng.poisson(embedding.n_runs * kb) {
+        features.push((rng.below(len), Feature::NRun));
+    }
+    features.sort_by_key(|&(start, _)| start);
+
+    let mut truth: Vec<(Interval, Feature)> = Vec::new();
+    for (start, feature) in features {
+        if truth.last().is_some_and(|(iv, _)| iv.end > start) {
+            continue;
+        }
+        let (unit, feature_len) = match feature {
+            Feature::NRun => (b"N".to_vec(), rng.range(embedding.min_n_length, embedding.max_n_length)),
+            Feature::Homopolymer => {
+                (vec![rng.base()], rng.range(embedding.min_length, embedding.max_length))
+            }
+            _ => {
+                let unit_len = rng.range(2, 6);
+                (rng.bases(unit_len), rng.range(embedding.min_length, embedding.max_length))
+            }
+        };
+        let end = (start + feature_len).min(len);
+        seq[start..end].copy_from_slice(&repeat_unit(&unit, end - start));
+        let feature = if feature == Feature::NRun { feature } else { Feature::of_unit(&unit) };
+        truth.push((Interval::new(start, end), feature));
+    }
+    SimulatedRead { seq, truth }
+}
+
+/// Complexity classes of `num_reads` reads: the given fractions of low and medium
+/// complexity reads first, then high complexity reads
+pub fn complexity_mix(num_reads: usize, low: f64, medium: f64) -> Vec<Complexity> {
+    let num_low = (num_reads as f64 * low) as usize;
+    let num_medium = (num_reads as f64 * medium) as usize;
+    let num_high = num_reads.saturating_sub(num_low + num_medium);
+    std::iter::repeat_n(Complexity::Low, num_low)
+        .chain(std::iter::repeat_n(Complexity::Medium, num_medium))
+        .chain(std::iter::repeat_n(Complexity::High, num_high))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_reproducible() {
+        let a: Vec<u64> = {
+            let mut rng = Rng::new(42);
+            (0..5).map(|_| rng.next_u64()).collect()
+        };
+        let mut rng = Rng::new(42);
+        assert_eq!(a, (0..5).map(|_| rng.next_u64()).collect::<Vec<_>>());
+        assert_ne!(Rng::new(43).next_u64(), a[0]);
+
+        for _ in 0..1000 {
+            assert!(rng.below(7) < 7);
+            assert!((0.0..1.0).contains(&rng.unit()));
+            assert!((4..=8).contains(&rng.range(4, 8)));
+        }
+    }
+
+    #[test]
+    fn test_sequences() {
+        let mut rng = Rng::new(1);
+        for complexity in [Complexity::Low, Complexity::Medium, Complexity::High] {
+            let seq = sequence(&mut rng, complexity, 150);
+            assert_eq!(seq.len(), 150);
+            assert!(seq.iter().all(|b| b"ACGT".contains(b)));
+        }
+        assert_eq!(repeat_unit(b"CAG", 7), b"CAGCAGC");
+
+        // Units are labelled by their primitive period
+        assert_eq!(Feature::of_unit(b"AAA"), Feature::Homopolymer);
+        assert_eq!(Feature::of_unit(b"AA"), Feature::Homopolymer);
+        assert_eq!(Feature::of_unit(b"ATAT"), Feature::Dinucleotide);
+        assert_eq!(Feature::of_unit(b"CAGCAG"), Feature::Trinucleotide);
+        assert_eq!(Feature::of_unit(b"ATATA"), Feature::TandemRepeat);
+        assert_eq!(Feature::of_unit(b"CAG"), Feature::Trinucleotide);
+        let mut rng = Rng::new(3);
+        for _ in 0..200 {
+            let read = simulate_read(&mut rng, Complexity::Low, 30, &Embedding::default());
+            let period = match read.truth[0].1 {
+                Feature::Homopolymer => 1,
+                Feature::Dinucleotide => 2,
+                Feature::Trinucleotide => 3,
+                feature => panic!("{:?} in a low-complexity read", feature),
+            };
+            assert!(read.seq.iter().zip(&read.seq[period..]).all(|(a, b)| a == b));
+            assert!(read.seq.iter().zip(&read.seq[1..]).any(|(a, b)| a != b) || period == 1);
+        }
+
+        let mut rng = Rng::new(7);
+        let embedding = Embedding {
+            homopolymers: 2.0,
+            tandem_repeats: 2.0,
+            n_runs: 1.0,
+            min_length: 20,
+            max_length: 40,
+            min_n_length: 1,
+            max_n_length: 5,
+        };
+        let mut features = 0;
+        for _ in 0..20 {
+            let read = simulate_read(&mut rng, Complexity::High, 1000, &embedding);
+            assert_eq!(read.seq.len(), 1000);
+            for window in read.truth.windows(2) {
+                assert!(window[0].0.end <= window[1].0.start);
+            }
+            for (iv, feature) in &read.truth {
+                let region = &read.seq[iv.start..iv.end];
+                match feature {
+                    Feature::NRun => assert!(region.iter().all(|&b| b == b'N')),
+                    Feature::Homopolymer => assert!(region.iter().all(|&b| b == region[0])),
+                    _ => assert!(region.iter().all(|&b| b"ACGT".contains(&b))),
+                }
+            }
+            features += read.truth.len();
+        }
+        // About 5 features per kilobase, minus overlaps
+        assert!((50..=150).contains(&features), "{} features", features);
+
+        let read = simulate_read(&mut rng, Complexity::Medium, 100, &embedding);
+        assert_eq!(read.truth, vec![(Interval::new(0, 100), Feature::TandemRepeat)]);
+        for model in [LengthModel::Uniform, LengthModel::Fixed, LengthModel::LogNormal] {
+            let mean = (0..2000).map(|_| model.sample(&mut rng, 1000)).sum::<usize>() as f64 / 2000.0;
+            assert!((900.0..1100.0).contains(&mean), "{:?}: mean {}", model, mean);
+        }
+
+        assert_eq!(parse_length_range("10-40"), Ok((10, 40)));
+        assert_eq!(parse_length_range("5"), Ok((5, 5)));
+        assert!(parse_length_range("40-10").is_err());
+        assert!(parse_length_range("0-5").is_err());
+
+        let mix = complexity_mix(10, 0.3, 0.3);
+        assert_eq!(mix.iter().filter(|&&c| c == Complexity::Low).count(), 3);
+        assert_eq!(mix.iter().filter(|&&c| c == Complexity::High).count(), 4);
+    }
+}
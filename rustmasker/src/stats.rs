@@ -0,0 +1,433 @@
+// Run statistics written by `--stats`
+//
+// Counters are accumulated sequentially in the write loop (or merged from
+// per-thread partial results) and serialized to JSON at the end of the run.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use serde_json::{json, Map, Value};
+
+use crate::homopolymer::{HomopolymerRun, MinRunLength, RunLengthHistogram, HOMOPOLYMER_BASES};
+use crate::tails::{PolyXTail, ReadEnd, TailConfig};
+
+/// Homopolymer detector statistics
+#[derive(Debug, Clone)]
+pub struct HomopolymerStats {
+    /// "mask" or "report"
+    pub mode: String,
+    pub min_run: MinRunLength,
+    /// Number of detected runs per base (A, C, G, T)
+    pub runs: [u64; 4],
+    /// Total bases in detected runs per base (A, C, G, T)
+    pub run_bases: [u64; 4],
+    /// Lengths of all runs (detected or not) per base
+    pub histogram: RunLengthHistogram,
+}
+
+impl HomopolymerStats {
+    pub fn new(mode: &str, min_run: MinRunLength) -> Self {
+        Self {
+            mode: mode.to_string(),
+            min_run,
+            runs: [0; 4],
+            run_bases: [0; 4],
+            histogram: RunLengthHistogram::new(),
+        }
+    }
+
+    /// Count the detected runs of one read
+    pub fn record_runs(&mut self, runs: &[HomopolymerRun]) {
+        for run in runs {
+            if let Some(i) = HOMOPOLYMER_BASES.iter().position(|&b| b == run.base) {
+                self.runs[i] += 1;
+                self.run_bases[i] += run.len() as u64;
+            }
+        }
+    }
+
+    /// Add the counts of statistics with the same settings; false if the settings differ
+    fn merge(&mut self, other: &HomopolymerStats) -> bool {
+        if self.mode != other.mode || self.min_run != other.min_run {
+            return false;
+        }
+        add_per_base(&mut self.runs, &other.runs);
+        add_per_base(&mut self.run_bases, &other.run_bases);
+        self.histogram.merge(&other.histogram);
+        true
+    }
+
+    fn to_json(&self) -> Value {
+        let per_base = |values: &[u64; 4]| -> Map<String, Value> {
+            HOMOPOLYMER_BASES
+                .iter()
+                .zip(values.iter())
+                .map(|(&b, &v)| ((b as char).to_string(), json!(v)))
+                .collect()
+        };
+        let histogram: Map<String, Value> = HOMOPOLYMER_BASES
+            .iter()
+            .map(|&b| ((b as char).to_string(), json!(self.histogram.to_map(b))))
+            .collect();
+
+        json!({
+            "mode": self.mode,
+            "min_run": self.min_run.to_string(),
+            "runs": per_base(&self.runs),
+            "run_bases": per_base(&self.run_bases),
+            "histogram": histogram,
+        })
+    }
+}
+
+/// Poly-X tail detector statistics
+#[derive(Debug, Clone)]
+pub struct PolyXStats {
+    /// "mask" or "trim"
+    pub mode: String,
+    pub config: TailConfig,
+    /// Reads with a 5' tail, per base (A, C, G, T)
+    pub five_prime_reads: [u64; 4],
+    /// Reads with a 3' tail, per base (A, C, G, T)
+    pub three_prime_reads: [u64; 4],
+    /// Total bases in detected tails
+    pub tail_bases: u64,
+}
+
+impl PolyXStats {
+    pub fn new(mode: &str, config: TailConfig) -> Self {
+        Self {
+            mode: mode.to_string(),
+            config,
+            five_prime_reads: [0; 4],
+            three_prime_reads: [0; 4],
+            tail_bases: 0,
+        }
+    }
+
+    /// Count the tails found in one read
+    pub fn record_tails(&mut self, tails: &[PolyXTail]) {
+        for tail in tails {
+            if let Some(i) = HOMOPOLYMER_BASES.iter().position(|&b| b == tail.base) {
+                match tail.read_end {
+                    ReadEnd::FivePrime => self.five_prime_reads[i] += 1,
+                    ReadEnd::ThreePrime => self.three_prime_reads[i] += 1,
+                }
+                self.tail_bases += tail.len() as u64;
+            }
+        }
+    }
+
+    /// Add the counts of statistics with the same settings; false if the settings differ
+    fn merge(&mut self, other: &PolyXStats) -> bool {
+        if self.mode != other.mode || self.config != other.config {
+            return false;
+        }
+        add_per_base(&mut self.five_prime_reads, &other.five_prime_reads);
+        add_per_base(&mut self.three_prime_reads, &other.three_prime_reads);
+        self.tail_bases += other.tail_bases;
+        true
+    }
+
+    fn to_json(&self) -> Value {
+        let per_base = |values: &[u64; 4]| -> Map<String, Value> {
+            HOMOPOLYMER_BASES
+                .iter()
+                .zip(values.iter())
+                .filter(|(b, _)| self.config.bases.contains(b))
+                .map(|(&b, &v)| ((b as char).to_string(), json!(v)))
+                .collect()
+        };
+
+        json!({
+            "mode": self.mode,
+            "bases": String::from_utf8_lossy(&self.config.bases),
+            "min_len": self.config.min_len,
+            "mismatch_rate": self.config.mismatch_rate,
+            "five_prime_reads": per_base(&self.five_prime_reads),
+            "three_prime_reads": per_base(&self.three_prime_reads),
+            "tail_bases": self.tail_bases,
+        })
+    }
+}
+
+/// Reads shorter than the entropy window
+#[derive(Debug, Clone)]
+pub struct ShortReadStats {
+    /// Short-read policy name (e.g. "whole-read")
+    pub policy: String,
+    pub window: usize,
+    /// Reads shorter than the window
+    pub reads: u64,
+    /// Short reads masked by entropy masking (i.e. by the policy)
+    pub masked_reads: u64,
+}
+
+impl ShortReadStats {
+    pub fn new(policy: &str, window: usize) -> Self {
+        Self {
+            policy: policy.to_string(),
+            window,
+            reads: 0,
+            masked_reads: 0,
+        }
+    }
+
+    /// Count one read (ignored unless it is shorter than the window), and whether
+    /// entropy masking contributed to its masked intervals
+    pub fn record_read(&mut self, len: usize, entropy_masked: bool) {
+        if len < self.window {
+            self.reads += 1;
+            self.masked_reads += entropy_masked as u64;
+        }
+    }
+
+    /// Add the counts of statistics with the same settings; false if the settings differ
+    fn merge(&mut self, other: &ShortReadStats) -> bool {
+        if self.policy != other.policy || self.window != other.window {
+            return false;
+        }
+        self.reads += other.reads;
+        self.masked_reads += other.masked_reads;
+        true
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "policy": self.policy,
+            "window": self.window,
+            "reads": self.reads,
+            "masked_reads": self.masked_reads,
+        })
+    }
+}
+
+fn add_per_base(totals: &mut [u64; 4], values: &[u64; 4]) {
+    for (total, value) in totals.iter_mut().zip(values) {
+        *total += value;
+    }
+}
+
+/// Merge a detector section of every file, or None if a file lacks it or has other settings
+fn merge_sections<T: Clone>(
+    files: &[FileStats],
+    section: impl Fn(&MaskingStats) -> &Option<T>,
+    merge: impl Fn(&mut T, &T) -> bool,
+) -> Option<T> {
+    let (first, rest) = files.split_first()?;
+    let mut total = section(&first.stats).clone()?;
+    for file in rest {
+        if !merge(&mut total, section(&file.stats).as_ref()?) {
+            return None;
+        }
+    }
+    Some(total)
+}
+
+/// Statistics for a whole run
+#[derive(Debug, Clone, Default)]
+pub struct MaskingStats {
+    pub reads: u64,
+    pub bases: u64,
+    /// Reads with at least one base masked
+    pub masked_reads: u64,
+    /// Bases changed to N by masking
+    pub masked_bases: u64,
+    pub homopolymer: Option<HomopolymerStats>,
+    pub poly_x: Option<PolyXStats>,
+    pub short_reads: Option<ShortReadStats>,
+    /// Effective options of the run (after presets, config file and command line)
+    pub config: Option<Value>,
+}
+
+impl MaskingStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one processed read
+    pub fn record_read(&mut self, len: usize, masked_bases: usize) {
+        self.reads += 1;
+        self.bases += len as u64;
+        if masked_bases > 0 {
+            self.masked_reads += 1;
+            self.masked_bases += masked_bases as u64;
+        }
+    }
+
+    /// Fraction of bases masked (0 if no bases were processed)
+    pub fn masked_fraction(&self) -> f64 {
+        if self.bases == 0 {
+            0.0
+        } else {
+            self.masked_bases as f64 / self.bases as f64
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "reads": self.reads,
+            "bases": self.bases,
+            "masked_reads": self.masked_reads,
+            "masked_bases": self.masked_bases,
+            "masked_fraction": self.masked_fraction(),
+        });
+        if let Some(homopolymer) = &self.homopolymer {
+            value["homopolymer"] = homopolymer.to_json();
+        }
+        if let Some(poly_x) = &self.poly_x {
+            value["poly_x"] = poly_x.to_json();
+        }
+        if let Some(short_reads) = &self.short_reads {
+            value["short_reads"] = short_reads.to_json();
+        }
+        if let Some(config) = &self.config {
+            value["config"] = config.clone();
+        }
+        value
+    }
+
+    /// Write statistics as pretty-printed JSON
+    pub fn write_json(&self, path: &str) -> std::io::Result<()> {
+        write_pretty_json(path, &self.to_json())
+    }
+}
+
+/// Statistics of one input file of a run with several inputs
+#[derive(Debug, Clone)]
+pub struct FileStats {
+    pub input: String,
+    /// Output file, if each input has its own
+    pub output: Option<String>,
+    pub stats: MaskingStats,
+}
+
+/// Statistics of a run with several inputs: totals, and full statistics per input file
+///
+/// The totals include the detector sections (homopolymer, poly_x, short_reads) and the
+/// effective options (config) only when every file has them with the same settings, which
+/// per-file manifest options can change.
+pub fn files_json(files: &[FileStats]) -> Value {
+    let mut total = MaskingStats::new();
+    for file in files {
+        total.reads += file.stats.reads;
+        total.bases += file.stats.bases;
+        total.masked_reads += file.stats.masked_reads;
+        total.masked_bases += file.stats.masked_bases;
+    }
+    total.homopolymer = merge_sections(files, |stats| &stats.homopolymer, HomopolymerStats::merge);
+    total.poly_x = merge_sections(files, |stats| &stats.poly_x, PolyXStats::merge);
+    total.short_reads = merge_sections(files, |stats| &stats.short_reads, ShortReadStats::merge);
+    total.config = merge_sections(files, |stats| &stats.config, |a, b| a == b);
+    let mut value = total.to_json();
+    value["files"] = files
+        .iter()
+        .map(|file| {
+            let mut entry = json!({ "input": file.input });
+            if let Some(output) = &file.output {
+                entry["output"] = json!(output);
+            }
+            entry["stats"] = file.stats.to_json();
+            entry
+        })
+        .collect();
+    value
+}
+
+/// Write per-file statistics as pretty-printed JSON
+pub fn write_files_json(path: &str, files: &[FileStats]) -> std::io::Result<()> {
+    write_pretty_json(path, &files_json(files))
+}
+
+fn write_pretty_json(path: &str, value: &Value) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homopolymer::find_homopolymer_runs;
+
+    #[test]
+    fn test_stats_json() {
+        let mut stats = MaskingStats::new();
+        let mut homopolymer = HomopolymerStats::new("report", MinRunLength::uniform(4));
+        let sequence = b"ACGTTTTTAC";
+        homopolymer.record_runs(&find_homopolymer_runs(sequence, &homopolymer.min_run));
+        homopolymer.histogram.add_sequence(sequence);
+        stats.homopolymer = Some(homopolymer);
+        stats.record_read(10, 0);
+        stats.record_read(10, 5);
+
+        let json = stats.to_json();
+        assert_eq!(json["reads"], 2);
+        assert_eq!(json["masked_reads"], 1);
+        assert_eq!(json["masked_fraction"], 0.25);
+        assert_eq!(json["homopolymer"]["runs"]["T"], 1);
+        assert_eq!(json["homopolymer"]["run_bases"]["T"], 5);
+        assert_eq!(json["homopolymer"]["histogram"]["T"]["5"], 1);
+        assert_eq!(json["homopolymer"]["histogram"]["A"]["1"], 2);
+        assert!(json.get("config").is_none());
+
+        // Short reads only count as masked when entropy masking (the policy) masked them
+        let mut short_reads = ShortReadStats::new("whole-read", 80);
+        short_reads.record_read(20, false);
+        short_reads.record_read(30, true);
+        short_reads.record_read(100, true);
+        assert_eq!((short_reads.reads, short_reads.masked_reads), (2, 1));
+
+        stats.config = Some(json!({ "window": 64, "preset": "sdust" }));
+        assert_eq!(stats.to_json()["config"]["window"], 64);
+    }
+
+    #[test]
+    fn test_files_json() {
+        let mut a = MaskingStats::new();
+        a.record_read(10, 5);
+        let mut b = MaskingStats::new();
+        b.record_read(30, 0);
+        let files = [
+            FileStats { input: "a.fq".to_string(), output: Some("out/a.fq".to_string()), stats: a },
+            FileStats { input: "b.fq".to_string(), output: None, stats: b },
+        ];
+        let json = files_json(&files);
+        assert_eq!(json["reads"], 2);
+        assert_eq!(json["masked_fraction"], 0.125);
+        assert_eq!(json["files"][0]["output"], "out/a.fq");
+        assert_eq!(json["files"][0]["stats"]["masked_bases"], 5);
+        assert!(json["files"][1].get("output").is_none());
+        assert!(json.get("short_reads").is_none());
+    }
+
+    #[test]
+    fn test_files_json_detector_totals() {
+        let file = |input: &str, window: usize, len: usize| {
+            let mut stats = MaskingStats::new();
+            let mut homopolymer = HomopolymerStats::new("report", MinRunLength::uniform(4));
+            let sequence = b"ACGTTTTTAC";
+            homopolymer.record_runs(&find_homopolymer_runs(sequence, &homopolymer.min_run));
+            homopolymer.histogram.add_sequence(sequence);
+            stats.homopolymer = Some(homopolymer);
+            let mut short_reads = ShortReadStats::new("whole-read", window);
+            short_reads.record_read(len, true);
+            stats.short_reads = Some(short_reads);
+            stats.config = Some(json!({ "window": window }));
+            FileStats { input: input.to_string(), output: None, stats }
+        };
+        let json = files_json(&[file("a.fq", 80, 20), file("b.fq", 80, 30)]);
+        assert_eq!(json["homopolymer"]["runs"]["T"], 2);
+        assert_eq!(json["homopolymer"]["histogram"]["T"]["5"], 2);
+        assert_eq!(json["short_reads"]["masked_reads"], 2);
+        assert_eq!(json["config"]["window"], 80);
+        assert!(json.get("poly_x").is_none());
+
+        // Sections with different settings are only reported per file
+        let json = files_json(&[file("a.fq", 80, 20), file("b.fq", 64, 30)]);
+        assert_eq!(json["homopolymer"]["runs"]["T"], 2);
+        assert!(json.get("short_reads").is_none());
+        assert!(json.get("config").is_none());
+    }
+}
@@ -0,0 +1,198 @@
+// Poly-X tail detection
+//
+// Two-colour Illumina chemistry produces poly-G tails where the signal drops
+// out, and cDNA/ONT reads carry poly-A (or poly-T on the reverse strand)
+// tails. These are found at the read ends directly, independently of the
+// windowed entropy scan, tolerating a configurable rate of mismatches.
+
+/// Read end a tail was found at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadEnd {
+    FivePrime,
+    ThreePrime,
+}
+
+/// A poly-X tail: [start, end) in read coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolyXTail {
+    /// Uppercase tail base
+    pub base: u8,
+    pub read_end: ReadEnd,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl PolyXTail {
+    /// Length of the tail in bases
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Tails are never empty, provided for API completeness
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+}
+
+/// Poly-X tail detection parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailConfig {
+    /// Tail bases to look for (uppercase, e.g. b"AGT")
+    pub bases: Vec<u8>,
+    /// Minimum tail length to report
+    pub min_len: usize,
+    /// Maximum fraction of mismatching bases within a tail
+    pub mismatch_rate: f64,
+    /// Look for tails at the 5' end
+    pub five_prime: bool,
+    /// Look for tails at the 3' end
+    pub three_prime: bool,
+}
+
+/// Length of the best poly-`base` tail at the start of `bases` (iterated from the read end inwards)
+///
+/// Uses cutadapt-style scoring: +1 for a matching base, -2 for a mismatch. The tail ends at
+/// the position with the highest score whose mismatch fraction is within `mismatch_rate`,
+/// so the inner boundary of a tail is always a matching base. The scan stops early once
+/// the remaining bases can no longer raise the score above the best seen.
+fn tail_length<I: ExactSizeIterator<Item = u8>>(bases: I, base: u8, mismatch_rate: f64) -> usize {
+    let total = bases.len();
+    let mut best_len = 0;
+    let mut best_score: i64 = 0;
+    let mut score: i64 = 0;
+    let mut errors = 0usize;
+
+    for (i, b) in bases.enumerate() {
+        let len = i + 1;
+        if b.to_ascii_uppercase() == base {
+            score += 1;
+        } else {
+            score -= 2;
+            errors += 1;
+        }
+        if score > best_score && errors as f64 <= mismatch_rate * len as f64 {
+            best_score = score;
+            best_len = len;
+        }
+        if score + ((total - len) as i64) <= best_score {
+            break;
+        }
+    }
+    best_len
+}
+
+/// Longest tail of any configured base at one end of the read
+fn best_tail(sequence: &[u8], config: &TailConfig, read_end: ReadEnd) -> Option<PolyXTail> {
+    let mut best: Option<PolyXTail> = None;
+    for &base in &config.bases {
+        let len = match read_end {
+            ReadEnd::FivePrime => tail_length(sequence.iter().copied(), base, config.mismatch_rate),
+            ReadEnd::ThreePrime => {
+                tail_length(sequence.iter().rev().copied(), base, config.mismatch_rate)
+            }
+        };
+        if len < config.min_len || best.is_some_and(|t| t.len() >= len) {
+            continue;
+        }
+        let (start, end) = match read_end {
+            ReadEnd::FivePrime => (0, len),
+            ReadEnd::ThreePrime => (sequence.len() - len, sequence.len()),
+        };
+        best = Some(PolyXTail { base, read_end, start, end });
+    }
+    best
+}
+
+/// Find poly-X tails at the 5' and 3' ends of a read
+///
+/// Returns (5' tail, 3' tail). If both tails are found and overlap (e.g. a read that is a
+/// single homopolymer), the 5' tail is shortened so the two never overlap, and dropped if
+/// that leaves it shorter than the minimum length.
+pub fn find_poly_x_tails(
+    sequence: &[u8],
+    config: &TailConfig,
+) -> (Option<PolyXTail>, Option<PolyXTail>) {
+    let three_prime = if config.three_prime {
+        best_tail(sequence, config, ReadEnd::ThreePrime)
+    } else {
+        None
+    };
+
+    let mut five_prime = if config.five_prime {
+        best_tail(sequence, config, ReadEnd::FivePrime)
+    } else {
+        None
+    };
+
+    if let (Some(five), Some(three)) = (five_prime.as_mut(), three_prime.as_ref()) {
+        five.end = five.end.min(three.start);
+        if five.len() < config.min_len {
+            five_prime = None;
+        }
+    }
+
+    (five_prime, three_prime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bases: &[u8]) -> TailConfig {
+        TailConfig {
+            bases: bases.to_vec(),
+            min_len: 8,
+            mismatch_rate: 0.1,
+            five_prime: true,
+            three_prime: true,
+        }
+    }
+
+    #[test]
+    fn test_three_prime_poly_a_with_mismatch() {
+        let sequence = b"ACGTACGTAGCTAGCTAAAAACAAAAAAAAAA";
+        let (five, three) = find_poly_x_tails(sequence, &config(b"AG"));
+        assert_eq!(five, None);
+        let three = three.unwrap();
+        assert_eq!(three.base, b'A');
+        assert_eq!(three.read_end, ReadEnd::ThreePrime);
+        // The tail extends through the single C but stops before the non-A sequence
+        assert_eq!(three.start, 16);
+        assert_eq!(three.end, sequence.len());
+    }
+
+    #[test]
+    fn test_five_prime_poly_t_and_min_len() {
+        let sequence = b"TTTTTTTTTTTGCATCGATCGGGG";
+        let (five, three) = find_poly_x_tails(sequence, &config(b"GT"));
+        assert_eq!(five.map(|t| (t.base, t.start, t.end)), Some((b'T', 0, 11)));
+        // GGGG is shorter than min_len
+        assert_eq!(three, None);
+    }
+
+    #[test]
+    fn test_mismatch_rate_at_read_end() {
+        let mut cfg = config(b"G");
+        cfg.min_len = 4;
+        cfg.mismatch_rate = 0.05;
+        let (_, three) = find_poly_x_tails(b"ACGTACGTGGGGGGGGGC", &cfg);
+        assert_eq!(three, None);
+        cfg.mismatch_rate = 0.1;
+        let (_, three) = find_poly_x_tails(b"ACGTACGTGGGGGGGGGC", &cfg);
+        assert_eq!(three.map(|t| (t.start, t.end)), Some((8, 18)));
+    }
+
+    #[test]
+    fn test_overlapping_tails_do_not_overlap() {
+        let sequence = b"AAAAAAAAAAAAAAAAAAAA";
+        let (five, three) = find_poly_x_tails(sequence, &config(b"A"));
+        assert_eq!(three.map(|t| (t.start, t.end)), Some((0, 20)));
+        assert_eq!(five, None);
+
+        // The 5' tail spans the read but is cut to 3 bases by the 3' tail, under min_len
+        let sequence = b"AATAAAAAAAAA";
+        let (five, three) = find_poly_x_tails(sequence, &config(b"A"));
+        assert_eq!(three.map(|t| (t.start, t.end)), Some((3, 12)));
+        assert_eq!(five, None);
+    }
+}
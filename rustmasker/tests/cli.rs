@@ -0,0 +1,210 @@
+// Tests of the rustmasker binary
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Write a test input to the temporary directory
+fn write_input(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rustmasker-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Run rustmasker with the given arguments
+fn rustmasker(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rustmasker")).args(args).output().unwrap()
+}
+
+#[test]
+fn test_stats_homopolymer_report() {
+    let input = write_input(
+        "stats.fq",
+        b"@read_1\nACGTAAAAAAAAAAAAGTCAGTCCGATGCA\n+\nIIIIIIIIIIIIIIIIIIIIIIIIIIIIII\n",
+    );
+    let output = rustmasker(&["stats", "-i", input.to_str().unwrap(), "--homopolymer", "report"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("no effect"));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["homopolymer"]["histogram"]["A"]["12"], 1);
+    std::fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn test_sam_masking_already_n() {
+    // Masking a read that is already all N still sets its qualities, as for FASTQ
+    let input = write_input(
+        "already_n.sam",
+        b"@HD\tVN:1.6\tSO:unknown\nread_1\t4\t*\t0\t0\t*\t*\t0\t0\tNNNNNNNNNN\tIIIIIIIIII\n",
+    );
+    let clean = std::env::temp_dir().join(format!("rustmasker-{}-clean.sam", std::process::id()));
+    let output = rustmasker(&[
+        "-i",
+        input.to_str().unwrap(),
+        "--short-reads",
+        "always",
+        "--clean-output",
+        clean.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let sam = String::from_utf8(output.stdout).unwrap();
+    assert!(sam.contains("read_1\t4\t*\t0\t0\t*\t*\t0\t0\tNNNNNNNNNN\t##########"));
+    // Masked, so not routed to the clean output
+    assert!(!std::fs::read_to_string(&clean).unwrap().contains("read_1"));
+    std::fs::remove_file(input).unwrap();
+    std::fs::remove_file(clean).unwrap();
+}
+
+#[test]
+fn test_sam_modification_calls_on_input_n() {
+    // Calls on bases that were N before masking are dropped along with the others
+    let input = write_input(
+        "mod_n.sam",
+        b"@HD\tVN:1.6\tSO:unknown\n\
+          read_1\t4\t*\t0\t0\t*\t*\t0\t0\tACGTNNNNNN\tIIIIIIIIII\tMM:Z:N+a?,1,4;C+m?,0;\tML:B:C,10,20,30\n",
+    );
+    let output = rustmasker(&["-i", input.to_str().unwrap(), "--short-reads", "always"]);
+    assert!(output.status.success());
+    let sam = String::from_utf8(output.stdout).unwrap();
+    assert!(sam.contains("\tNNNNNNNNNN\t##########\tMM:Z:N+a?;C+m?;\t"));
+    std::fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn test_compare_exit_status() {
+    let first = write_input("compare_1.fq", b"@read_1\nACGTNNNNAC\n+\nIIII####II\n");
+    let second = write_input("compare_2.fq", b"@read_1\nACGTNNNNNN\n+\nIIII######\n");
+    let (first, second) = (first.to_str().unwrap(), second.to_str().unwrap());
+
+    assert_eq!(rustmasker(&["compare", first, first]).status.code(), Some(0));
+    assert_eq!(rustmasker(&["compare", first, second]).status.code(), Some(1));
+    // Errors are told apart from differences
+    let missing = rustmasker(&["compare", first, "missing.fq"]);
+    assert_eq!(missing.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&missing.stderr).starts_with("Error: "));
+    let other = write_input("compare_3.fq", b"@read_2\nACGTNNNNAC\n+\nIIII####II\n");
+    assert_eq!(rustmasker(&["compare", first, other.to_str().unwrap()]).status.code(), Some(2));
+
+    for path in [first, second, other.to_str().unwrap()] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_preset_flags_can_be_switched_off() {
+    let input = write_input("preset.fq", b"@read_1\nACGTACGTAC\n+\nIIIIIIIIII\n");
+    let config = write_input("preset.toml", b"preset = \"sdust\"\nsdust = false\n");
+    let output = rustmasker(&[
+        "stats",
+        "-i",
+        input.to_str().unwrap(),
+        "--config",
+        config.to_str().unwrap(),
+        "--entropy",
+    ]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["config"]["no_entropy"], false);
+    assert_eq!(json["config"]["sdust"], false);
+    std::fs::remove_file(input).unwrap();
+    std::fs::remove_file(config).unwrap();
+}
+
+#[test]
+fn test_no_detectors() {
+    let input = write_input("no_detectors.fq", b"@read_1\nAAAAAAAAAAAAAAAAAAAA\n+\nIIIIIIIIIIIIIIIIIIII\n");
+    let output = rustmasker(&["-i", input.to_str().unwrap(), "--no-entropy"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no masking detector"));
+    // Trimming needs no detector
+    let output = rustmasker(&["-i", input.to_str().unwrap(), "--no-entropy", "--poly-x", "trim"]);
+    assert!(output.status.success());
+    std::fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn test_window_not_larger_than_k() {
+    let input = write_input("window_k.fq", b"@read_1\nACGTACGTACGTACGTACGT\n+\nIIIIIIIIIIIIIIIIIIII\n");
+    let bedgraph = std::env::temp_dir().join(format!("rustmasker-{}-window_k.bg", std::process::id()));
+    for window in ["5", "3"] {
+        let output = rustmasker(&[
+            "mask", "-i", input.to_str().unwrap(), "-w", window, "-k", "5",
+            "--entropy-bedgraph", bedgraph.to_str().unwrap(),
+        ]);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("must be larger than k=5"));
+        assert!(!stderr.contains("panicked"));
+    }
+    std::fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn test_fastq_headers_kept_byte_for_byte() {
+    // Header and comment bytes that are not UTF-8 and a repeated header on the '+' line
+    let input = write_input(
+        "headers.fq",
+        b"@read_\xe9\xff comment \xfe\x80\tBC:Z:1\nACGTTGCAAGCTTCGATCGGATCCTAGCTA\n\
+          +read_\xe9\xff comment \xfe\x80\tBC:Z:1\nIIIIIIIIIIIIIIIIIIIIIIIIIIIIII\n\
+          @read_\xc3\x28 2\nAAAAAAAAAAAAAAAAAAAA\n+read_\xc3\x28 2\nIIIIIIIIIIIIIIIIIIII\n",
+    );
+    let output = rustmasker(&["-i", input.to_str().unwrap(), "--keep-plus"]);
+    assert!(output.status.success());
+    let expected: &[u8] = b"@read_\xe9\xff comment \xfe\x80\tBC:Z:1\nACGTTGCAAGCTTCGATCGGATCCTAGCTA\n\
+          +read_\xe9\xff comment \xfe\x80\tBC:Z:1\nIIIIIIIIIIIIIIIIIIIIIIIIIIIIII\n\
+          @read_\xc3\x28 2\nNNNNNNNNNNNNNNNNNNNN\n+read_\xc3\x28 2\n####################\n";
+    assert_eq!(output.stdout, expected);
+    std::fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn test_split_outputs() {
+    let input = write_input(
+        "split.fq",
+        b"@clean\nACGTTGCAAGCTTCGATCGG\n+\nIIIIIIIIIIIIIIIIIIII\n\
+          @partial\nACGTTGCAAAAAAAAAATCGG\n+\nIIIIIIIIIIIIIIIIIIIII\n\
+          @full\nAAAAAAAAAAAA\n+\nIIIIIIIIIIII\n\
+          @all_n\nNNNNNNNNNNNN\n+\nIIIIIIIIIIII\n",
+    );
+    let split = |name: &str| std::env::temp_dir().join(format!("rustmasker-{}-{}", std::process::id(), name));
+    let (clean, partial, full) = (split("clean.fq"), split("partial.fq"), split("full.fq"));
+    let output = rustmasker(&[
+        "-i",
+        input.to_str().unwrap(),
+        "--no-entropy",
+        "--homopolymer",
+        "mask",
+        "--homopolymer-min-run",
+        "8",
+        "--clean-output",
+        clean.to_str().unwrap(),
+        "--partial-output",
+        partial.to_str().unwrap(),
+        "--full-output",
+        full.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    // A read that was all N before masking is clean unless a detector masked it
+    let ids = |path: &PathBuf| -> Vec<String> {
+        let text = std::fs::read_to_string(path).unwrap();
+        text.lines().step_by(4).map(String::from).collect()
+    };
+    assert_eq!(ids(&clean), ["@clean", "@all_n"]);
+    assert_eq!(ids(&partial), ["@partial"]);
+    assert_eq!(ids(&full), ["@full"]);
+    for path in [input, clean, partial, full] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_presets_with_stats() {
+    let input = write_input("presets.fq", b"@read_1\nACGTTGCAAAAAAAAAAAAAATCGG\n+\nIIIIIIIIIIIIIIIIIIIIIIIII\n");
+    for preset in ["bbmask", "sdust", "dustmasker", "ont-strict"] {
+        let output = rustmasker(&["stats", "-i", input.to_str().unwrap(), "--preset", preset]);
+        assert!(output.status.success(), "{}: {}", preset, String::from_utf8_lossy(&output.stderr));
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["config"]["preset"], preset);
+        assert_eq!(json["reads"], 1);
+    }
+    std::fs::remove_file(input).unwrap();
+}